@@ -0,0 +1,107 @@
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+use rand::Rng;
+
+pub const PAGE_SIZE: usize = 256;
+
+// A 256-byte page of plain memory that a device lets the system access directly.
+pub enum Page {
+    ReadOnly(*const u8),
+    ReadWrite(*mut u8),
+}
+
+// A memory region or peripheral attached to the system bus. The system routes reads and writes
+// for each address to the last attached device whose ranges contain it. Devices are Send so a
+// whole console can be moved to another thread.
+pub trait BusDevice: Any + Send {
+    // Address ranges the device responds to.
+    fn ranges(&self) -> Vec<RangeInclusive<u16>>;
+
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Pages of plain memory the system may read (and for ReadWrite pages, write) directly instead
+    /// of calling read and write, given as (page number, page) pairs.
+    ///
+    /// # Safety
+    ///
+    /// Implementations must return pointers to PAGE_SIZE bytes that stay valid through any number
+    /// of calls to read, write and tick, up to the first write after which take_remap returns
+    /// true. The memory must not be reallocated or referenced elsewhere in that time.
+    ///
+    /// Callers may only use the pointers until the device is dropped, reports a remap, or is
+    /// borrowed mutably other than through read, write and tick, and not while it's borrowed.
+    unsafe fn pages(&mut self) -> Vec<(u8, Page)> {
+        Vec::new()
+    }
+
+    // Return true if the pages returned by pages() have changed since it was last called, for
+    // instance after a bank switch. Checked after every write that goes to the device.
+    fn take_remap(&mut self) -> bool {
+        false
+    }
+
+    // Advance the device by the given number of clock cycles (at the normal-speed rate of 4.19
+    // MHz) and return the interrupts it requests as IF bits.
+    fn tick(&mut self, _cycles: usize) -> u8 {
+        0
+    }
+
+    // Used to get back at the concrete device type. Wrappers should forward these to the wrapped
+    // device so it can still be found by type.
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// Plain read/write memory, such as work RAM and high RAM.
+pub struct Ram {
+    base: u16,
+    data: Vec<u8>,
+}
+
+impl Ram {
+    // Create a RAM device of the given size starting at base, filled with junk from rng as on
+    // startup.
+    pub fn new(base: u16, size: usize, rng: &mut impl Rng) -> Self {
+        Self {
+            base,
+            data: (0..size).map(|_| rng.random()).collect(),
+        }
+    }
+}
+
+impl BusDevice for Ram {
+    fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        vec![self.base..=self.base + (self.data.len() - 1) as u16]
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        self.data[(addr - self.base) as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.data[(addr - self.base) as usize] = data;
+    }
+
+    unsafe fn pages(&mut self) -> Vec<(u8, Page)> {
+        // Only pages that lie entirely within the RAM can be mapped directly.
+        let base = self.base as usize;
+        let first = base.div_ceil(PAGE_SIZE);
+        let last = (base + self.data.len()) / PAGE_SIZE;
+        (first..last)
+            .map(|page| {
+                let ptr = self.data[page * PAGE_SIZE - base..].as_mut_ptr();
+                (page as u8, Page::ReadWrite(ptr))
+            })
+            .collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,78 @@
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+use rand::Rng;
+
+use crate::bus::{BusDevice, Page, PAGE_SIZE};
+
+const ROM_SIZE: usize = 32 * 1024;
+const EXTRAM_SIZE: usize = 8 * 1024;
+
+// Cartridge ROM and external RAM.
+pub struct Cartridge {
+    rom: Vec<u8>,
+    extram: Vec<u8>,
+}
+
+impl Default for Cartridge {
+    fn default() -> Self {
+        Self::new(&mut rand::rng())
+    }
+}
+
+impl Cartridge {
+    // Create an empty cartridge slot, with external RAM filled with junk from rng.
+    pub fn new(rng: &mut impl Rng) -> Self {
+        Self {
+            rom: vec![0; ROM_SIZE],
+            extram: (0..EXTRAM_SIZE).map(|_| rng.random()).collect(),
+        }
+    }
+
+    // Load a ROM image. Only 32 KiB ROMs without a memory bank controller are supported.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        if rom.len() != ROM_SIZE {
+            return Err(format!("unsupported ROM size {} (only 32 KiB ROMs are supported)", rom.len()));
+        }
+        self.rom.copy_from_slice(rom);
+        Ok(())
+    }
+}
+
+impl BusDevice for Cartridge {
+    fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        vec![0x0000..=0x7FFF, 0xA000..=0xBFFF]
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x8000 => self.rom[addr as usize],
+            0xA000..0xC000 => self.extram[addr as usize - 0xA000],
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let 0xA000..0xC000 = addr {
+            self.extram[addr as usize - 0xA000] = data;
+        }
+    }
+
+    unsafe fn pages(&mut self) -> Vec<(u8, Page)> {
+        let rom = self.rom.chunks_exact(PAGE_SIZE)
+            .enumerate()
+            .map(|(i, page)| (i as u8, Page::ReadOnly(page.as_ptr())));
+        let extram = self.extram.chunks_exact_mut(PAGE_SIZE)
+            .enumerate()
+            .map(|(i, page)| (0xA0 + i as u8, Page::ReadWrite(page.as_mut_ptr())));
+        rom.chain(extram).collect()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
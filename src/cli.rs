@@ -0,0 +1,83 @@
+// Command line handling shared by the windowed (rgb) and headless (rgb-headless) frontends.
+
+use crate::GameBoy;
+use crate::colorize::Combo;
+use crate::image::Screenshot;
+use crate::model::Model;
+use crate::palette::Palette;
+use crate::ppu::Renderer;
+
+// Options both frontends take.
+pub struct Options {
+    pub model: Model,
+    pub renderer: Renderer,
+    pub combo: Option<Combo>,       // Buttons held at boot to pick a DMG game's colors on a CGB
+    pub screenshot: Screenshot,     // How to render frames: palette, filter and scaling
+    pub record: Option<String>,     // Where to save a video
+    pub seed: Option<u64>,          // Seed for the junk memory contents, random if None
+    pub rom_path: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            model: Model::default(),
+            renderer: Renderer::default(),
+            combo: None,
+            screenshot: Screenshot::default(),
+            record: None,
+            seed: None,
+            rom_path: String::from("ball.gb"),
+        }
+    }
+}
+
+impl Options {
+    // Handle a command line argument, taking its value from args if it has one. Anything that
+    // isn't an option is taken as the ROM path.
+    pub fn parse_arg(&mut self, arg: String, args: &mut impl Iterator<Item = String>) -> Result<(), String> {
+        match arg.as_str() {
+            "--model" => self.model = option_value(args, &arg)?.parse()?,
+            "--fifo" => self.renderer = Renderer::Fifo,
+            "--palette" => {
+                let name = option_value(args, &arg)?;
+                self.screenshot.palette = Palette::preset(&name).map_or_else(|| Palette::load(&name), Ok)?;
+            },
+            "--combo" => self.combo = Some(option_value(args, &arg)?.parse()?),
+            "--filter" => self.screenshot.filter = option_value(args, &arg)?.parse()?,
+            "--screenshot-scale" => self.screenshot.scale = parse_number(&option_value(args, &arg)?)?.max(1),
+            "--indexed" => self.screenshot.indexed = true,
+            "--record" => self.record = Some(option_value(args, &arg)?),
+            "--seed" => self.seed = Some(parse_number(&option_value(args, &arg)?)? as u64),
+            _ => self.rom_path = arg,
+        }
+        Ok(())
+    }
+
+    // Create the console and insert the ROM.
+    pub fn load(&self) -> Result<GameBoy, String> {
+        let rom = std::fs::read(&self.rom_path).map_err(|e| format!("{}: {}", self.rom_path, e))?;
+        let mut gameboy = match self.seed {
+            Some(seed) => GameBoy::with_seed(self.model, seed),
+            None => GameBoy::new(self.model),
+        };
+        gameboy.system_mut().boot_combo = self.combo;
+        gameboy.system_mut().ppu_mut().set_renderer(self.renderer);
+        gameboy.load_cartridge(&rom).map_err(|e| format!("{}: {}", self.rom_path, e))?;
+        Ok(gameboy)
+    }
+}
+
+// Get the value following a command line option.
+pub fn option_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} requires an argument", option))
+}
+
+// Parse a decimal or 0x-prefixed hexadecimal number.
+pub fn parse_number(s: &str) -> Result<usize, String> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    result.map_err(|_| format!("invalid number '{}'", s))
+}
@@ -0,0 +1,189 @@
+// Automatic colorization of monochrome games on a CGB. The CGB boot ROM identifies Nintendo
+// games by a checksum of the header title and loads a set of color palettes for them, which the
+// game's DMG palette registers then index into. Holding a direction (and optionally A or B)
+// while the logo is shown picks one of twelve alternative sets instead.
+
+use std::str::FromStr;
+
+// Colors used by the palette sets, four at a time, as RGB555.
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000,
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B,
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000,
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// Palette sets, as the offsets into COLORS of the OBJ0, OBJ1 and BG palettes. A few of them
+// straddle two groups of four colors.
+const SETS: [(usize, usize, usize); 51] = [
+    (16, 16, 116), (72, 72, 72), (80, 80, 80), (96, 96, 96), (36, 36, 36),
+    (0, 0, 0), (108, 108, 108), (20, 20, 20), (48, 48, 48), (104, 104, 104),
+    (64, 32, 32), (16, 112, 112), (16, 8, 8), (12, 16, 16), (16, 116, 116),
+    (112, 16, 112), (8, 68, 8), (64, 64, 32), (16, 16, 28), (16, 16, 72),
+    (16, 16, 80), (76, 76, 36), (15, 15, 44), (68, 68, 8), (16, 16, 8),
+    (16, 16, 12), (112, 112, 0), (12, 12, 0), (0, 0, 4), (72, 88, 72),
+    (80, 88, 80), (96, 88, 96), (64, 88, 32), (68, 16, 52), (111, 0, 56),
+    (111, 16, 60), (76, 88, 36), (64, 112, 40), (16, 92, 112), (68, 88, 8),
+    (16, 0, 8), (16, 112, 12), (112, 12, 0), (12, 112, 16), (84, 112, 16),
+    (12, 112, 0), (100, 12, 112), (0, 112, 32), (16, 12, 112), (112, 12, 24),
+    (16, 112, 116),
+];
+
+// Title checksums of the recognized games. The last ones are shared by several titles, which
+// are told apart by the fourth letter of the title.
+const CHECKSUMS: [u8; 79] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, 0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+];
+const FIRST_SHARED: usize = 65;
+
+// Fourth title letters for the shared checksums. If the first row doesn't match, the same
+// checksum is tried against the next row.
+const LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Palette set for each checksum, followed by one for each entry of LETTERS.
+const SET_FOR_CHECKSUM: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50, 17,
+    46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+// Button combination held during the boot logo to override the palette set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Combo {
+    Up,
+    UpA,
+    UpB,
+    Left,
+    LeftA,
+    LeftB,
+    Down,
+    DownA,
+    DownB,
+    Right,
+    RightA,
+    RightB,
+}
+
+impl Combo {
+    fn set(self) -> usize {
+        match self {
+            Combo::Up => 5,
+            Combo::UpA => 43,
+            Combo::UpB => 28,
+            Combo::Left => 48,
+            Combo::LeftA => 40,
+            Combo::LeftB => 7,
+            Combo::Down => 8,
+            Combo::DownA => 3,
+            Combo::DownB => 49,
+            Combo::Right => 1,
+            Combo::RightA => 0,
+            Combo::RightB => 6,
+        }
+    }
+}
+
+impl FromStr for Combo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "up" => Ok(Combo::Up),
+            "up+a" => Ok(Combo::UpA),
+            "up+b" => Ok(Combo::UpB),
+            "left" => Ok(Combo::Left),
+            "left+a" => Ok(Combo::LeftA),
+            "left+b" => Ok(Combo::LeftB),
+            "down" => Ok(Combo::Down),
+            "down+a" => Ok(Combo::DownA),
+            "down+b" => Ok(Combo::DownB),
+            "right" => Ok(Combo::Right),
+            "right+a" => Ok(Combo::RightA),
+            "right+b" => Ok(Combo::RightB),
+            _ => Err(format!("unknown button combination '{}'", s)),
+        }
+    }
+}
+
+// Get the sum of the title bytes of a cartridge header (0x0100-0x014F), which the boot ROM uses
+// to recognize games. Only games published by Nintendo are checked, for others this is None.
+pub fn title_checksum(header: &[u8]) -> Option<u8> {
+    let licensee = header[0x4B];
+    if licensee != 0x01 && !(licensee == 0x33 && &header[0x44..0x46] == b"01") {
+        return None;
+    }
+    Some(header[0x34..0x44].iter().fold(0u8, |sum, &b| sum.wrapping_add(b)))
+}
+
+// The BG, OBJ0 and OBJ1 color palettes loaded for a monochrome game, as RGB555.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Colorization {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl Colorization {
+    // Pick the palettes for a cartridge given its header (0x0100-0x014F), as the boot ROM does.
+    pub fn from_header(header: &[u8], combo: Option<Combo>) -> Self {
+        let set = match combo {
+            Some(combo) => combo.set(),
+            None => SET_FOR_CHECKSUM[Self::checksum_index(header)] as usize,
+        };
+        let (obj0, obj1, bg) = SETS[set];
+        let palette = |offset: usize| COLORS[offset..offset + 4].try_into().unwrap();
+        Self { bg: palette(bg), obj0: palette(obj0), obj1: palette(obj1) }
+    }
+
+    // Find the game in the checksum table, or return 0 (the default set) if it isn't one.
+    fn checksum_index(header: &[u8]) -> usize {
+        let Some(checksum) = title_checksum(header) else {
+            return 0;
+        };
+        let title = &header[0x34..0x44];
+        let Some(index) = CHECKSUMS.iter().position(|&c| c == checksum) else {
+            return 0;
+        };
+        if index < FIRST_SHARED {
+            return index;
+        }
+
+        (index - FIRST_SHARED..LETTERS.len())
+            .step_by(CHECKSUMS.len() - FIRST_SHARED)
+            .find(|&i| LETTERS[i] == title[3])
+            .map_or(0, |i| FIRST_SHARED + i)
+    }
+}
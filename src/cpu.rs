@@ -1,5 +1,28 @@
+use crate::colorize;
+use crate::model::Model;
 use crate::system::System;
 
+// Number of clock cycles taken by each opcode. Conditional jumps, calls and returns list the
+// not-taken cost; the extra cycles for a taken branch are added where the branch is executed.
+const CYCLES: [usize; 256] = [
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+     8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+     8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16,
+     8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16,
+    12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16,
+    12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16,
+];
+
 #[derive(Default, Debug)]
 pub struct CPU {
     a: u8,
@@ -23,10 +46,50 @@ pub struct CPU {
 }
 
 impl CPU {
-    pub fn new() -> Self {
-        let mut cpu = Self::default();
-        cpu.pc = 0x100;
-        cpu.sp = 0xfffe;
+    // Create a CPU in the state the boot ROM of the system's model leaves it in when it jumps to
+    // the cartridge entry point.
+    pub fn new(system: &System) -> Self {
+        let mut cpu = Self {
+            pc: 0x100,
+            sp: 0xfffe,
+            ..Default::default()
+        };
+        let (af, bc, de, hl) = match system.model {
+            Model::DMG0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::DMG => (0x0180, 0x0013, 0x00D8, 0x014D),
+            Model::MGB => (0xFF80, 0x0013, 0x00D8, 0x014D),
+            Model::SGB => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::SGB2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            // A monochrome game on a color model. The boot ROM leaves the title checksum it
+            // looked the game up by in B. The AGB boot ROM then increments B, setting the flags.
+            Model::CGB | Model::AGB if system.read(0x0143) & 0x80 == 0 => {
+                let header: Vec<u8> = (0x0100..0x0150).map(|addr| system.read(addr)).collect();
+                let (b, hl) = match colorize::title_checksum(&header) {
+                    Some(checksum) => (checksum, 0x991A),
+                    None => (0, 0x007C),
+                };
+                if system.model == Model::AGB {
+                    let b = b.wrapping_add(1);
+                    let flags = ((b == 0) as u16) << 7 | ((b & 0x0F == 0) as u16) << 5;
+                    (0x1100 | flags, (b as u16) << 8, 0x0008, hl)
+                } else {
+                    (0x1180, (b as u16) << 8, 0x0008, hl)
+                }
+            },
+            Model::CGB => (0x1180, 0x0000, 0xFF56, 0x000D),
+            Model::AGB => (0x1100, 0x0100, 0xFF56, 0x000D),
+        };
+        cpu.set_af(af);
+        cpu.set_bc(bc);
+        cpu.set_de(de);
+        cpu.set_hl(hl);
+
+        // The DMG and MGB boot ROMs leave the carry and half-carry flags set unless the header
+        // checksum is zero.
+        if matches!(system.model, Model::DMG | Model::MGB) && system.read(0x014D) != 0 {
+            cpu.hf = true;
+            cpu.cf = true;
+        }
         cpu
     }
 
@@ -203,7 +266,20 @@ impl CPU {
         self.cf = self.a < value;
     }
 
-    pub fn execute_next(&mut self, system: &mut System) {
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Execute the next instruction (or service an interrupt) and return the number of clock cycles
+    // it took.
+    pub fn execute_next(&mut self, system: &mut System) -> usize {
+        // The CPU sits idle during DMA transfers and speed switches.
+        let stall = system.take_stall();
+        if stall > 0 {
+            return stall;
+        }
+
+        let mut cycles = 0;
         if self.ime {
             let interrupt_enable = system.read(0xFFFF);
             let interrupt_flags = system.read(0xFF0F);
@@ -217,16 +293,28 @@ impl CPU {
                 self.pc = 0x40 + 8 * interrupt_number as u16;
                 self.ime = false;
                 self.halted = false;
+                cycles += 20;
             }
         }
 
         if self.halted {
-            return;
+            return cycles + 4;
         }
 
-        match self.fetch8(system) {
+        let opcode = self.fetch8(system);
+        cycles += CYCLES[opcode as usize];
+
+        match opcode {
             0x00 => (),
-            0x10 | 0x76 => self.halted = true,
+            0x10 => {
+                // STOP is followed by a padding byte. If a speed switch is armed, it performs the
+                // switch instead of stopping.
+                self.pc = self.pc.wrapping_add(1);
+                if !system.stop() {
+                    self.halted = true;
+                }
+            },
+            0x76 => self.halted = true,
 
             // Flag setting operations (SCF/CCF/DI/EI)
             0x2F => {
@@ -373,33 +461,37 @@ impl CPU {
                 let offset = self.fetch8(system) as i8 as u16;
                 if !self.zf {
                     self.pc = self.pc.wrapping_add(offset);
+                    cycles += 4;
                 }
             },
             0x28 => {
                 let offset = self.fetch8(system) as i8 as u16;
                 if self.zf {
                     self.pc = self.pc.wrapping_add(offset);
+                    cycles += 4;
                 }
             },
             0x30 => {
                 let offset = self.fetch8(system) as i8 as u16;
                 if !self.cf {
                     self.pc = self.pc.wrapping_add(offset);
+                    cycles += 4;
                 }
             },
             0x38 => {
                 let offset = self.fetch8(system) as i8 as u16;
                 if self.cf {
                     self.pc = self.pc.wrapping_add(offset);
+                    cycles += 4;
                 }
             },
 
             // Jump absolute (JP)
-            0xC2 => if !self.zf { self.pc = self.fetch16(system) },
+            0xC2 => if !self.zf { self.pc = self.fetch16(system); cycles += 4 },
             0xC3 => self.pc = self.fetch16(system),
-            0xCA => if self.zf { self.pc = self.fetch16(system) },
-            0xD2 => if !self.cf { self.pc = self.fetch16(system) },
-            0xDA => if self.cf { self.pc = self.fetch16(system) },
+            0xCA => if self.zf { self.pc = self.fetch16(system); cycles += 4 },
+            0xD2 => if !self.cf { self.pc = self.fetch16(system); cycles += 4 },
+            0xDA => if self.cf { self.pc = self.fetch16(system); cycles += 4 },
             0xE9 => self.pc = system.read(self.hl()) as u16
                               | (system.read(self.hl().wrapping_add(1)) as u16) << 8,
 
@@ -409,6 +501,7 @@ impl CPU {
                 if !self.zf {
                     self.push16(system, self.pc);
                     self.pc = addr;
+                    cycles += 12;
                 }
             },
             0xCC => {
@@ -416,6 +509,7 @@ impl CPU {
                 if self.zf {
                     self.push16(system, self.pc);
                     self.pc = addr;
+                    cycles += 12;
                 }
             },
             0xCD => {
@@ -428,6 +522,7 @@ impl CPU {
                 if !self.cf {
                     self.push16(system, self.pc);
                     self.pc = addr;
+                    cycles += 12;
                 }
             },
             0xDC => {
@@ -435,15 +530,16 @@ impl CPU {
                 if self.cf {
                     self.push16(system, self.pc);
                     self.pc = addr;
+                    cycles += 12;
                 }
             },
 
             // Return from subroutine (RET)
-            0xC0 => if !self.zf { self.pc = self.pop16(system) },
-            0xC8 => if self.zf { self.pc = self.pop16(system) },
+            0xC0 => if !self.zf { self.pc = self.pop16(system); cycles += 12 },
+            0xC8 => if self.zf { self.pc = self.pop16(system); cycles += 12 },
             0xC9 => self.pc = self.pop16(system),
-            0xD0 => if !self.cf { self.pc = self.pop16(system) },
-            0xD8 => if self.cf { self.pc = self.pop16(system) },
+            0xD0 => if !self.cf { self.pc = self.pop16(system); cycles += 12 },
+            0xD8 => if self.cf { self.pc = self.pop16(system); cycles += 12 },
             0xD9 => {
                 self.pc = self.pop16(system);
                 self.ime = true;
@@ -671,7 +767,7 @@ impl CPU {
             // Misc 16-bit loads (LD)
             0x08 => {
                 let addr = self.fetch16(system);
-                system.write(addr, self.sp as u8 & 0xff);
+                system.write(addr, self.sp as u8);
                 system.write(addr.wrapping_add(1), (self.sp >> 8) as u8);
             },
             0xF8 => {
@@ -682,5 +778,7 @@ impl CPU {
 
             opc => unimplemented!("opcode 0x{:02x} at 0x{:04x} -- {:?}", opc, self.pc.wrapping_sub(1), self),
         }
+
+        cycles
     }
 }
@@ -0,0 +1,123 @@
+// Frames as output by the PPU, recording what each pixel shows independently of how it's
+// displayed. Converting them to RGBA is left to the frontend.
+
+use crate::palette::Palette;
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// The DMG palette register a shade was picked through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Bg,     // BGP, for the background and window
+    Obj0,   // OBP0
+    Obj1,   // OBP1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Pixel {
+    Shade(Layer, u8),   // One of the four shades of non-color mode, from 0 (lightest) to 3
+    Rgb555(u16),        // A color from the color mode palettes
+}
+
+impl Default for Pixel {
+    fn default() -> Self {
+        Pixel::Shade(Layer::Bg, 0)
+    }
+}
+
+impl Pixel {
+    // Get the RGBA color of the pixel, using the given colors for the non-color mode shades.
+    pub fn to_rgba(self, palette: &Palette) -> [u8; 4] {
+        match self {
+            Pixel::Shade(Layer::Bg, shade) => palette.bg[shade as usize],
+            Pixel::Shade(Layer::Obj0, shade) => palette.obj0[shade as usize],
+            Pixel::Shade(Layer::Obj1, shade) => palette.obj1[shade as usize],
+            Pixel::Rgb555(rgb) => {
+                let scale = |c: u16| ((c << 3) | (c >> 2)) as u8;
+                [scale(rgb & 0x1F), scale((rgb >> 5) & 0x1F), scale((rgb >> 10) & 0x1F), 0xff]
+            },
+        }
+    }
+}
+
+// Convert pixels to RGBA in out, which must hold 4 bytes for each.
+fn pixels_to_rgba(pixels: &[Pixel], palette: &Palette, out: &mut [u8]) {
+    assert_eq!(out.len(), pixels.len() * 4);
+    for (rgba, pixel) in out.chunks_exact_mut(4).zip(pixels) {
+        rgba.copy_from_slice(&pixel.to_rgba(palette));
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FrameBuffer {
+    pixels: Vec<Pixel>,
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameBuffer {
+    pub const WIDTH: usize = SCREEN_WIDTH;
+    pub const HEIGHT: usize = SCREEN_HEIGHT;
+
+    pub fn new() -> Self {
+        Self { pixels: vec![Pixel::default(); Self::WIDTH * Self::HEIGHT] }
+    }
+
+    // Get the pixels in reading order.
+    pub fn pixels(&self) -> &[Pixel] {
+        &self.pixels
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Pixel) {
+        self.pixels[y * Self::WIDTH + x] = pixel;
+    }
+
+    pub fn fill(&mut self, pixel: Pixel) {
+        self.pixels.fill(pixel);
+    }
+
+    // Get a hash of the frame contents that stays the same between runs and builds (64-bit
+    // FNV-1a), for checking output against a known frame.
+    pub fn hash(&self) -> u64 {
+        let bytes = self.pixels.iter().flat_map(|pixel| match *pixel {
+            Pixel::Shade(layer, shade) => [0, layer as u8, shade],
+            Pixel::Rgb555(rgb) => [1, rgb as u8, (rgb >> 8) as u8],
+        });
+        bytes.fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    // Convert the frame to RGBA pixels in out, which must hold WIDTH * HEIGHT * 4 bytes.
+    pub fn to_rgba(&self, palette: &Palette, out: &mut [u8]) {
+        pixels_to_rgba(&self.pixels, palette, out);
+    }
+}
+
+// A picture of any size made of PPU pixels, such as the debugging views of VRAM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Pixel>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: vec![Pixel::default(); width * height] }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Pixel) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    pub fn fill(&mut self, pixel: Pixel) {
+        self.pixels.fill(pixel);
+    }
+
+    // Convert the image to RGBA pixels in out, which must hold width * height * 4 bytes.
+    pub fn to_rgba(&self, palette: &Palette, out: &mut [u8]) {
+        pixels_to_rgba(&self.pixels, palette, out);
+    }
+}
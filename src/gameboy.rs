@@ -0,0 +1,119 @@
+use crate::cpu::CPU;
+use crate::frame::FrameBuffer;
+use crate::model::Model;
+use crate::ppu::CYCLES_PER_FRAME;
+use crate::system::System;
+
+// A complete console: the CPU together with the system it runs on.
+pub struct GameBoy {
+    cpu: CPU,
+    system: System,
+    cycles: usize,  // Clock cycles into the current frame
+    frames: u64,    // Frames completed since startup
+    seed: Option<u64>,  // Seed for the junk memory starts out with, random if None
+}
+
+impl GameBoy {
+    // Create a console of the given model, with an empty cartridge slot.
+    pub fn new(model: Model) -> Self {
+        Self::create(model, None)
+    }
+
+    // Create a console like new(), with memory that starts out as junk filled the same way on
+    // every run with the same seed.
+    pub fn with_seed(model: Model, seed: u64) -> Self {
+        Self::create(model, Some(seed))
+    }
+
+    fn create(model: Model, seed: Option<u64>) -> Self {
+        let system = System::new(model, seed);
+        let cpu = CPU::new(&system);
+        Self { cpu, system, cycles: 0, frames: 0, seed }
+    }
+
+    // Insert a cartridge ROM image and start it from the post-boot state, as if the console had
+    // been switched off and on again. Only the model, boot button combination, renderer and layer
+    // toggles are kept; devices attached to the old system are dropped. On error, the console is
+    // left as it was.
+    pub fn load_cartridge(&mut self, rom: &[u8]) -> Result<(), String> {
+        let mut system = System::new(self.system.model, self.seed);
+        system.boot_combo = self.system.boot_combo;
+        let ppu = self.system.ppu();
+        let (renderer, layers) = (ppu.renderer(), ppu.layers);
+        system.ppu_mut().set_renderer(renderer);
+        system.ppu_mut().layers = layers;
+        system.load_rom(rom)?;
+
+        self.system = system;
+        self.cpu = CPU::new(&self.system);
+        self.cycles = 0;
+        self.frames = 0;
+        Ok(())
+    }
+
+    // Execute one instruction (or interrupt dispatch) and return how many clock cycles it took.
+    pub fn step_instruction(&mut self) -> usize {
+        // In double speed mode the CPU gets twice as many cycles per frame.
+        let elapsed = self.cpu.execute_next(&mut self.system) / self.system.speed_factor();
+        self.system.tick(elapsed);
+        self.cycles += elapsed;
+        if self.cycles >= CYCLES_PER_FRAME {
+            self.cycles -= CYCLES_PER_FRAME;
+            self.frames += 1;
+        }
+        elapsed
+    }
+
+    // Run until the end of the current frame.
+    pub fn run_frame(&mut self) {
+        let frame = self.frames;
+        while self.frames == frame {
+            self.step_instruction();
+        }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Get the game title from the cartridge header. On color games the last byte of the title area
+    // is the CGB flag, and shorter titles are padded with NULs.
+    pub fn title(&self) -> String {
+        let end = if self.system.read(0x0143) & 0x80 != 0 { 0x0143 } else { 0x0144 };
+        let title: Vec<u8> = (0x0134..end).map(|addr| self.system.read(addr)).collect();
+        let end = title.iter().position(|&c| c == 0).unwrap_or(title.len());
+        title[..end].iter().map(|&c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '?' }).collect()
+    }
+
+    // Set the held buttons, as a combination of the joypad::BUTTON_* bits.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.system.joypad_mut().set_buttons(buttons);
+    }
+
+    // Get the last frame the PPU completed.
+    pub fn frame_buffer(&self) -> &FrameBuffer {
+        self.system.ppu().frame()
+    }
+
+    // Take the audio samples produced since the last call. There is no sound emulation yet, so
+    // this is always empty.
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        Vec::new()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn system(&self) -> &System {
+        &self.system
+    }
+
+    pub fn system_mut(&mut self) -> &mut System {
+        &mut self.system
+    }
+}
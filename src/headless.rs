@@ -0,0 +1,190 @@
+// Running without a window, for CI and batch jobs: run for a set time or until a condition is
+// met, optionally with scripted input, then save the final frame and print its hash. This is its
+// own program so it builds without the GUI dependencies.
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use rgb::GameBoy;
+use rgb::cli::{self, option_value, parse_number};
+use rgb::joypad;
+use rgb::video::VideoRecorder;
+
+// Process exit codes. 1 is used for bad arguments.
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_TIMEOUT: i32 = 2;
+const EXIT_FAULT: i32 = 3;
+
+// Condition that ends a run early.
+#[derive(Clone, Copy, Debug)]
+enum Until {
+    Pc(u16),        // The CPU is about to execute the instruction at this address
+    Mem(u16, u8),   // The byte at this address has this value
+}
+
+struct Options {
+    frames: Option<usize>,  // Stop after this many frames
+    cycles: Option<usize>,  // Stop after this many clock cycles
+    until: Option<Until>,   // Stop when this is true, failing if a limit is reached first
+    input: Option<String>,  // Input script
+    png: Option<String>,    // Where to save the final frame
+    common: cli::Options,   // Options shared with the windowed frontend
+}
+
+impl Until {
+    fn parse_pc(s: &str) -> Result<Self, String> {
+        Ok(Until::Pc(parse_number(s)? as u16))
+    }
+
+    // Parse ADDR=VALUE.
+    fn parse_mem(s: &str) -> Result<Self, String> {
+        let (addr, value) = s.split_once('=').ok_or(format!("expected ADDR=VALUE, found '{}'", s))?;
+        Ok(Until::Mem(parse_number(addr)? as u16, parse_number(value)? as u8))
+    }
+
+    fn reached(self, gameboy: &GameBoy) -> bool {
+        match self {
+            Until::Pc(pc) => gameboy.cpu().pc() == pc,
+            Until::Mem(addr, value) => gameboy.system().read(addr) == value,
+        }
+    }
+}
+
+// Load an input script. Each line gives a frame number and the buttons held from that frame on,
+// like "120 a+start" or "130 none". Anything after a '#' is a comment.
+fn load_script(path: &str) -> Result<Vec<(usize, u8)>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut script = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parse = || {
+            let (frame, buttons) = line.split_once(char::is_whitespace).ok_or("expected FRAME BUTTONS")?;
+            Ok::<_, String>((parse_number(frame)?, joypad::parse_buttons(buttons.trim())?))
+        };
+        script.push(parse().map_err(|e| format!("{}:{}: {}", path, i + 1, e))?);
+    }
+    script.sort_by_key(|&(frame, _)| frame);
+    Ok(script)
+}
+
+// Run the console and return the process exit code.
+fn run(gameboy: &mut GameBoy, options: &Options) -> i32 {
+    let script = match options.input.as_deref().map(load_script).transpose() {
+        Ok(script) => script.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        },
+    };
+    let mut recorder = match options.common.record.as_deref().map(|path| {
+        let (width, height, _) = options.common.screenshot.render(gameboy.frame_buffer());
+        VideoRecorder::create(Path::new(path), width, height).map_err(|e| format!("{}: {}", path, e))
+    }).transpose() {
+        Ok(recorder) => recorder,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        },
+    };
+    let max_frames = options.frames.unwrap_or(usize::MAX) as u64;
+    let max_cycles = options.cycles.unwrap_or(usize::MAX);
+
+    // Panics in the emulator (such as on an unimplemented opcode) count as a CPU fault.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| -> io::Result<bool> {
+        let mut next_input = script.iter().peekable();
+        let mut cycles = 0;
+        loop {
+            let frame = gameboy.frames();
+            while let Some(&(_, buttons)) = next_input.next_if(|&&(f, _)| f as u64 <= frame) {
+                gameboy.set_buttons(buttons);
+            }
+            if options.until.is_some_and(|until| until.reached(gameboy)) {
+                return Ok(true);
+            }
+            if frame >= max_frames || cycles >= max_cycles {
+                return Ok(false);
+            }
+            cycles += gameboy.step_instruction();
+            if let Some(recorder) = &mut recorder
+                && gameboy.frames() != frame {
+                recorder.add_frame(&options.common.screenshot.render(gameboy.frame_buffer()).2)?;
+            }
+        }
+    }));
+    let result = match result {
+        Ok(Err(e)) => {
+            eprintln!("{}: {}", options.common.record.as_deref().unwrap_or_default(), e);
+            return 1;
+        },
+        Ok(Ok(done)) => Ok(done),
+        Err(e) => Err(e),
+    };
+    if let Some(recorder) = recorder
+        && let Err(e) = recorder.finish() {
+        eprintln!("{}: {}", options.common.record.as_deref().unwrap_or_default(), e);
+        return 1;
+    }
+
+    let frame = gameboy.frame_buffer();
+    if let Some(path) = &options.png
+        && let Err(e) = options.common.screenshot.save(frame, Path::new(path)) {
+        eprintln!("{}: {}", path, e);
+        return 1;
+    }
+    println!("{:016x}", frame.hash());
+
+    match result {
+        Ok(true) => EXIT_SUCCESS,
+        Ok(false) if options.until.is_none() => EXIT_SUCCESS,
+        Ok(false) => EXIT_TIMEOUT,
+        Err(_) => EXIT_FAULT,
+    }
+}
+
+// Parse the command line.
+fn parse_args() -> Result<Options, String> {
+    // Usage: rgb-headless (--frames N | --cycles N) [--until-pc ADDR] [--until-mem ADDR=VALUE]
+    //                     [--input SCRIPT] [--png FILE] [--model MODEL] [--fifo]
+    //                     [--palette PRESET|FILE] [--combo BUTTONS] [--filter FILTER]
+    //                     [--screenshot-scale N] [--indexed] [--record FILE] [--seed N] [ROM]
+    let mut options = Options {
+        frames: None,
+        cycles: None,
+        until: None,
+        input: None,
+        png: None,
+        // Runs start from the same memory contents every time unless a seed is given, so their
+        // results can be checked.
+        common: cli::Options { seed: Some(0), ..cli::Options::default() },
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => options.frames = Some(parse_number(&option_value(&mut args, &arg)?)?),
+            "--cycles" => options.cycles = Some(parse_number(&option_value(&mut args, &arg)?)?),
+            "--until-pc" => options.until = Some(Until::parse_pc(&option_value(&mut args, &arg)?)?),
+            "--until-mem" => options.until = Some(Until::parse_mem(&option_value(&mut args, &arg)?)?),
+            "--input" => options.input = Some(option_value(&mut args, &arg)?),
+            "--png" => options.png = Some(option_value(&mut args, &arg)?),
+            _ => options.common.parse_arg(arg, &mut args)?,
+        }
+    }
+    if options.frames.is_none() && options.cycles.is_none() {
+        return Err(String::from("--frames or --cycles is required"));
+    }
+    Ok(options)
+}
+
+fn main() {
+    let (mut gameboy, options) = parse_args()
+        .and_then(|options| Ok((options.common.load()?, options)))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    std::process::exit(run(&mut gameboy, &options));
+}
@@ -0,0 +1,144 @@
+// Saving frames as image files.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::frame::{FrameBuffer, Pixel};
+use crate::palette::Palette;
+use crate::scale::Filter;
+
+// Save RGBA pixels as a PNG file.
+pub fn save_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Save 2-bit palette indices (one per byte) as an indexed-color PNG file with the given palette.
+pub fn save_indexed_png(path: &Path, width: usize, height: usize, indices: &[u8], palette: &[[u8; 4]; 4]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Two);
+    encoder.set_palette(palette.iter().flat_map(|rgba| [rgba[0], rgba[1], rgba[2]]).collect::<Vec<_>>());
+    let mut writer = encoder.write_header()?;
+    let packed: Vec<u8> = indices
+        .chunks_exact(width)
+        .flat_map(|row| row.chunks(4).map(|p| p.iter().enumerate().fold(0, |b, (i, &c)| b | c << (6 - 2 * i))))
+        .collect();
+    writer.write_image_data(&packed)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Get a file name made of a game title and the current time (in UTC), with the given extension.
+pub fn timestamped_name(title: &str, extension: &str) -> String {
+    let title: String = title
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let title = if title.is_empty() { "untitled" } else { &title };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    format!("{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.{}",
+        title, year, month, day, hour, minute, second, now.subsec_millis(), extension)
+}
+
+// Settings for saving the screen contents.
+#[derive(Clone, Copy, Debug)]
+pub struct Screenshot {
+    pub palette: Palette,   // Colors of the non-color mode shades
+    pub filter: Filter,     // Scaling filter applied first
+    pub scale: usize,       // Further nearest-neighbor scaling
+    pub indexed: bool,      // Also save the shades as an indexed-color image
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Self { palette: Palette::default(), filter: Filter::None, scale: 1, indexed: false }
+    }
+}
+
+impl Screenshot {
+    // Get a file name for a screenshot of the game with the given title, taken now.
+    pub fn file_name(title: &str) -> String {
+        timestamped_name(title, "png")
+    }
+
+    // Render a frame as RGBA pixels, returning the width, height and pixels.
+    pub fn render(&self, frame: &FrameBuffer) -> (usize, usize, Vec<u8>) {
+        let (width, height) = (FrameBuffer::WIDTH, FrameBuffer::HEIGHT);
+        let mut rgba = vec![0; width * height * 4];
+        frame.to_rgba(&self.palette, &mut rgba);
+        let n = self.filter.factor();
+        let mut filtered = vec![0; rgba.len() * n * n];
+        self.filter.apply(&rgba, width, height, &mut filtered);
+        let (width, height) = (width * n, height * n);
+        (width * self.scale, height * self.scale, self.upscale(&filtered, width, height, 4))
+    }
+
+    // Save a frame as a PNG file. With `indexed` set, the shades are also saved as palette
+    // indices 0-3 to a second file with "-indexed" added to the name, which fails for frames
+    // with color mode pixels.
+    pub fn save(&self, frame: &FrameBuffer, path: &Path) -> io::Result<()> {
+        let (width, height, rgba) = self.render(frame);
+        save_png(path, width, height, &rgba)?;
+
+        if self.indexed {
+            let shades = frame.pixels().iter().map(|pixel| match pixel {
+                Pixel::Shade(_, shade) => Ok(*shade),
+                Pixel::Rgb555(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "frame has color mode pixels")),
+            });
+            let shades = shades.collect::<io::Result<Vec<u8>>>()?;
+            let (width, height) = (FrameBuffer::WIDTH, FrameBuffer::HEIGHT);
+            let indices = self.upscale(&shades, width, height, 1);
+            save_indexed_png(&Self::indexed_path(path), width * self.scale, height * self.scale, &indices, &self.palette.bg)?;
+        }
+        Ok(())
+    }
+
+    fn indexed_path(path: &Path) -> PathBuf {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}-indexed.png", stem))
+    }
+
+    // Scale up an image with `bpp` bytes per pixel by repeating each pixel.
+    fn upscale(&self, src: &[u8], width: usize, height: usize, bpp: usize) -> Vec<u8> {
+        let n = self.scale;
+        let mut out = Vec::with_capacity(src.len() * n * n);
+        for y in 0..height * n {
+            let row = &src[(y / n) * width * bpp..(y / n + 1) * width * bpp];
+            for pixel in row.chunks_exact(bpp) {
+                for _ in 0..n {
+                    out.extend_from_slice(pixel);
+                }
+            }
+        }
+        out
+    }
+}
+
+// Convert days since 1970-01-01 to a (year, month, day) date, using Howard Hinnant's algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
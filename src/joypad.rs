@@ -0,0 +1,109 @@
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+use crate::bus::BusDevice;
+
+// Button bits, as passed to set_buttons. A set bit means the button is held.
+pub const BUTTON_RIGHT: u8 = 0x01;
+pub const BUTTON_LEFT: u8 = 0x02;
+pub const BUTTON_UP: u8 = 0x04;
+pub const BUTTON_DOWN: u8 = 0x08;
+pub const BUTTON_A: u8 = 0x10;
+pub const BUTTON_B: u8 = 0x20;
+pub const BUTTON_SELECT: u8 = 0x40;
+pub const BUTTON_START: u8 = 0x80;
+
+// P1 bits. The select bits and the button state are active low.
+const P1_DIRECTIONS: u8 = 0x10;
+const P1_ACTIONS: u8 = 0x20;
+
+const IRQ_JOYPAD: u8 = 0x10;
+
+// Parse a list of button names separated by '+', like "a+start", into button bits. "none"
+// means no buttons.
+pub fn parse_buttons(s: &str) -> Result<u8, String> {
+    if s.eq_ignore_ascii_case("none") {
+        return Ok(0);
+    }
+    s.split('+').try_fold(0, |buttons, name| {
+        let button = match name.to_ascii_lowercase().as_str() {
+            "right" => BUTTON_RIGHT,
+            "left" => BUTTON_LEFT,
+            "up" => BUTTON_UP,
+            "down" => BUTTON_DOWN,
+            "a" => BUTTON_A,
+            "b" => BUTTON_B,
+            "select" => BUTTON_SELECT,
+            "start" => BUTTON_START,
+            _ => return Err(format!("unknown button '{}'", name)),
+        };
+        Ok(buttons | button)
+    })
+}
+
+// The joypad register (P1) and the buttons behind it.
+pub struct Joypad {
+    select: u8,     // P1 bits 4-5, choosing which buttons are read
+    buttons: u8,    // Held buttons
+    irq: u8,        // Pending interrupt requests (IF bits)
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self { select: 0, buttons: 0, irq: 0 }
+    }
+
+    // Set which buttons are held. Pressing a button in a selected group requests the joypad
+    // interrupt, as its input line going low would.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        let before = self.lines();
+        self.buttons = buttons;
+        if before & !self.lines() & 0x0F != 0 {
+            self.irq |= IRQ_JOYPAD;
+        }
+    }
+
+    // Get the state of the P1 input lines for the selected buttons (0 = pressed).
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & P1_DIRECTIONS == 0 {
+            pressed |= self.buttons & 0x0F;
+        }
+        if self.select & P1_ACTIONS == 0 {
+            pressed |= self.buttons >> 4;
+        }
+        !pressed & 0x0F
+    }
+}
+
+impl BusDevice for Joypad {
+    fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        vec![0xFF00..=0xFF00]
+    }
+
+    fn read(&self, _addr: u16) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    fn write(&mut self, _addr: u16, data: u8) {
+        self.select = data & (P1_DIRECTIONS | P1_ACTIONS);
+    }
+
+    fn tick(&mut self, _cycles: usize) -> u8 {
+        std::mem::take(&mut self.irq)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
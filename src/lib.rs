@@ -0,0 +1,20 @@
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod bus;
+pub mod cartridge;
+pub mod cli;
+pub mod colorize;
+pub mod cpu;
+pub mod frame;
+pub mod gameboy;
+pub mod image;
+pub mod joypad;
+pub mod model;
+pub mod palette;
+pub mod postprocess;
+pub mod ppu;
+pub mod scale;
+pub mod system;
+pub mod video;
+
+pub use gameboy::GameBoy;
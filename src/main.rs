@@ -1,30 +1,92 @@
+use std::fmt::Display;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
 use pixels::{Pixels, SurfaceTexture};
 use winit::dpi::LogicalSize;
 use winit::error::EventLoopError;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::WindowBuilder;
 
-mod cpu;
-mod ppu;
-mod system;
+mod viewer;
 
-use crate::cpu::CPU;
-use crate::ppu::SCANLINES;
-use crate::system::System;
+use rgb::GameBoy;
+use rgb::cli;
+use rgb::image::{self, Screenshot};
+use rgb::joypad::{BUTTON_A, BUTTON_B, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_SELECT, BUTTON_START, BUTTON_UP};
+use rgb::palette::Palette;
+use rgb::postprocess::PostProcess;
+use rgb::scale::Filter;
+use rgb::video::VideoRecorder;
+
+use crate::viewer::{View, Viewer};
 
 const WIDTH: u32 = 160;
 const HEIGHT: u32 = 144;
 const MICROS_PER_FRAME: u64 = 1_000_000 / 60;
 
+// Get the joypad button a key is mapped to.
+fn joypad_button(key: KeyCode) -> Option<u8> {
+    match key {
+        KeyCode::ArrowRight => Some(BUTTON_RIGHT),
+        KeyCode::ArrowLeft => Some(BUTTON_LEFT),
+        KeyCode::ArrowUp => Some(BUTTON_UP),
+        KeyCode::ArrowDown => Some(BUTTON_DOWN),
+        KeyCode::KeyX => Some(BUTTON_A),
+        KeyCode::KeyZ => Some(BUTTON_B),
+        KeyCode::Backspace => Some(BUTTON_SELECT),
+        KeyCode::Enter => Some(BUTTON_START),
+        _ => None,
+    }
+}
+
+// Unwrap a result, or exit with the error.
+fn or_exit<T, E: Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+// Parse the command line into the options shared with the headless frontend and the
+// post-processing settings.
+fn parse_args() -> Result<(cli::Options, PostProcess), String> {
+    // Usage: rgb [--model MODEL] [--fifo] [--palette PRESET|FILE] [--combo BUTTONS]
+    //            [--blend PERSISTENCE] [--color-correction none|cgb|gba] [--filter FILTER]
+    //            [--screenshot-scale N] [--indexed] [--record FILE] [--seed N] [ROM]
+    let mut options = cli::Options::default();
+    let mut postprocess = PostProcess::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--blend" => {
+                let factor = cli::option_value(&mut args, &arg)?;
+                postprocess.set_persistence(factor.parse().map_err(|_| format!("invalid persistence '{}'", factor))?);
+            },
+            "--color-correction" => postprocess.set_correction(cli::option_value(&mut args, &arg)?.parse()?),
+            _ => options.parse_arg(arg, &mut args)?,
+        }
+    }
+    Ok((options, postprocess))
+}
+
 fn main() -> Result<(), EventLoopError> {
+    let (options, mut postprocess) = or_exit(parse_args());
+    let mut gameboy = or_exit(options.load());
+    let Screenshot { mut palette, mut filter, .. } = options.screenshot;
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
+    // The window is 3x the screen size, or larger if the filter needs it.
+    let window_size = |filter: Filter| {
+        let scale = filter.factor().max(3) as f64;
+        LogicalSize::new(WIDTH as f64 * scale, HEIGHT as f64 * scale)
+    };
     let window = {
-        let size = LogicalSize::new(WIDTH as f64 * 3.0, HEIGHT as f64 * 3.0);
+        let size = window_size(filter);
         WindowBuilder::new()
             .with_title("rgb")
             .with_inner_size(size)
@@ -36,24 +98,143 @@ fn main() -> Result<(), EventLoopError> {
     let mut pixels = {
         let winsize = window.inner_size();
         let surface = SurfaceTexture::new(winsize.width, winsize.height, &window);
-        Pixels::new(WIDTH, HEIGHT, surface).unwrap()
+        let factor = filter.factor() as u32;
+        Pixels::new(WIDTH * factor, HEIGHT * factor, surface).unwrap()
     };
+    let mut rgba = vec![0; WIDTH as usize * HEIGHT as usize * 4];
 
     let mut fps_counter = 0;
     let mut fps_time = Instant::now();
     let mut last_frame_time = Instant::now();
+    let mut preset = 0;
+    let mut buttons = 0;
+    let mut viewer: Option<Viewer> = None;
 
-    let mut cpu = CPU::new();
-    let mut system = System::new();
-    system.load_rom("ball.gb");
+    // Frames are recorded with the settings in use when recording started, so the size stays the
+    // same.
+    let start_recording = |path: &str, screenshot: Screenshot, gameboy: &GameBoy| {
+        let (width, height, _) = screenshot.render(gameboy.frame_buffer());
+        match VideoRecorder::create(Path::new(path), width, height) {
+            Ok(recorder) => {
+                println!("recording to {}", path);
+                Some((path.to_string(), screenshot, recorder))
+            },
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                None
+            },
+        }
+    };
+    let stop_recording = |(path, _, recorder): (String, Screenshot, VideoRecorder)| {
+        match recorder.finish() {
+            Ok(()) => println!("saved {}", path),
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    };
+    let mut recording = options.record.as_deref()
+        .and_then(|path| start_recording(path, Screenshot { palette, filter, ..options.screenshot }, &gameboy));
 
     event_loop.run(|event, elwt| {
         match event {
-            Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+            Event::WindowEvent { window_id, event: WindowEvent::CloseRequested } if window_id == window.id() => {
+                elwt.exit();
+            },
+            // However the loop ends, finish any recording in progress.
+            Event::LoopExiting => {
+                if let Some(recording) = recording.take() {
+                    stop_recording(recording);
+                }
+            },
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput {
+                    event: KeyEvent { physical_key: PhysicalKey::Code(key), state, repeat: false, .. },
+                    ..
+                },
+                ..
+            } => {
+                if let Some(button) = joypad_button(key) {
+                    if state == ElementState::Pressed {
+                        buttons |= button;
+                    } else {
+                        buttons &= !button;
+                    }
+                    gameboy.set_buttons(buttons);
+                } else if state == ElementState::Pressed {
+                    match key {
+                        // P cycles through the built-in palettes.
+                        KeyCode::KeyP => {
+                            preset = (preset + 1) % Palette::PRESETS.len();
+                            palette = Palette::PRESETS[preset].1;
+                        },
+                        // C cycles through the color correction modes.
+                        KeyCode::KeyC => postprocess.set_correction(postprocess.correction().next()),
+                        // F cycles through the scaling filters.
+                        KeyCode::KeyF => {
+                            filter = filter.next();
+                            let factor = filter.factor() as u32;
+                            if pixels.resize_buffer(WIDTH * factor, HEIGHT * factor).is_err() {
+                                eprintln!("pixels resize error");
+                                elwt.exit();
+                            }
+                            let _ = window.request_inner_size(window_size(filter));
+                        },
+                        // S saves a screenshot.
+                        KeyCode::KeyS => {
+                            let screenshot = Screenshot { palette, filter, ..options.screenshot };
+                            let path = Screenshot::file_name(&gameboy.title());
+                            match screenshot.save(gameboy.frame_buffer(), Path::new(&path)) {
+                                Ok(()) => println!("saved {}", path),
+                                Err(e) => eprintln!("{}: {}", path, e),
+                            }
+                        },
+                        // V opens the VRAM and OAM viewer, then switches between its views and closes it.
+                        KeyCode::KeyV => {
+                            let view = viewer.take().map_or(Some(View::Tiles), |viewer| viewer.view.next());
+                            viewer = view.and_then(|view| Viewer::new(view, &gameboy, elwt)
+                                .map_err(|e| eprintln!("viewer window error: {}", e))
+                                .ok());
+                        },
+                        // 1-4 toggle hiding the background, window and sprites, and forcing the
+                        // background to color 0.
+                        KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 | KeyCode::Digit4 => {
+                            let layers = &mut gameboy.system_mut().ppu_mut().layers;
+                            let (toggle, name) = match key {
+                                KeyCode::Digit1 => (&mut layers.hide_bg, "hide background"),
+                                KeyCode::Digit2 => (&mut layers.hide_window, "hide window"),
+                                KeyCode::Digit3 => (&mut layers.hide_sprites, "hide sprites"),
+                                _ => (&mut layers.force_bg_color0, "force background color 0"),
+                            };
+                            *toggle = !*toggle;
+                            println!("{}: {}", name, if *toggle { "on" } else { "off" });
+                        },
+                        // O prints all OAM entries.
+                        KeyCode::KeyO => {
+                            for sprite in gameboy.system().ppu().sprite_info() {
+                                println!("{}", sprite);
+                            }
+                        },
+                        // R starts or stops recording a video.
+                        KeyCode::KeyR => match recording.take() {
+                            Some(recording) => stop_recording(recording),
+                            None => {
+                                let path = image::timestamped_name(&gameboy.title(), "png");
+                                recording = start_recording(&path, Screenshot { palette, filter, ..options.screenshot }, &gameboy);
+                            },
+                        },
+                        _ => (),
+                    }
+                }
+            },
+            Event::WindowEvent { window_id, event } if viewer.as_ref().is_some_and(|viewer| viewer.id() == window_id) => {
+                viewer = viewer.take().and_then(|mut viewer| viewer.handle_event(&event).then_some(viewer));
+            },
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. }
+                if pixels.resize_surface(size.width, size.height).is_err() => {
+                eprintln!("pixels resize error");
                 elwt.exit();
             },
             Event::WindowEvent { event: WindowEvent::RedrawRequested, .. } => {
-                if let Err(_) = pixels.render() {
+                if pixels.render().is_err() {
                     eprintln!("pixels render error");
                     elwt.exit();
                 }
@@ -65,19 +246,23 @@ fn main() -> Result<(), EventLoopError> {
                     fps_time = Instant::now();
                 }
             },
-            Event::AboutToWait => {
-                // Limit framerate to 60 FPS.
-                if last_frame_time.elapsed() >= Duration::from_micros(MICROS_PER_FRAME) {
-                    last_frame_time = Instant::now();
-
-                    for _ in 0..SCANLINES {
-                        system.draw_scanline(pixels.frame_mut());
-                        for _ in 0..1000 {
-                            cpu.execute_next(&mut system);
-                        }
-                    }
-                    window.request_redraw();
+            // Limit framerate to 60 FPS.
+            Event::AboutToWait if last_frame_time.elapsed() >= Duration::from_micros(MICROS_PER_FRAME) => {
+                last_frame_time = Instant::now();
+
+                gameboy.run_frame();
+                if let Some((path, screenshot, recorder)) = &mut recording
+                    && let Err(e) = recorder.add_frame(&screenshot.render(gameboy.frame_buffer()).2) {
+                    eprintln!("{}: {}", path, e);
+                    stop_recording(recording.take().unwrap());
+                }
+                if let Some(viewer) = &mut viewer {
+                    viewer.update(&gameboy, &palette);
                 }
+                gameboy.frame_buffer().to_rgba(&palette, &mut rgba);
+                postprocess.apply(&mut rgba);
+                filter.apply(&rgba, WIDTH as usize, HEIGHT as usize, pixels.frame_mut());
+                window.request_redraw();
             },
             _ => ()
         }
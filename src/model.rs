@@ -0,0 +1,39 @@
+use std::str::FromStr;
+
+// Game Boy hardware models. The model determines the register state left behind by the boot ROM
+// and which hardware features are present.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    DMG0,   // Early original Game Boy
+    #[default]
+    DMG,    // Original Game Boy
+    MGB,    // Game Boy Pocket
+    SGB,    // Super Game Boy
+    SGB2,   // Super Game Boy 2
+    CGB,    // Game Boy Color
+    AGB,    // Game Boy Advance
+}
+
+impl Model {
+    // Whether the model has Game Boy Color hardware (VRAM DMA, double speed, color palettes).
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "DMG0" => Ok(Model::DMG0),
+            "DMG" => Ok(Model::DMG),
+            "MGB" => Ok(Model::MGB),
+            "SGB" => Ok(Model::SGB),
+            "SGB2" => Ok(Model::SGB2),
+            "CGB" => Ok(Model::CGB),
+            "AGB" => Ok(Model::AGB),
+            _ => Err(format!("unknown model '{}'", s)),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// Colors used to display the four shades of gray in non-color mode.
+
+// RGBA color of each shade, from lightest to darkest.
+pub type Shades = [[u8; 4]; 4];
+
+const GREEN: Shades = [
+    [0x9b, 0xbc, 0x0f, 0xff],
+    [0x8b, 0xac, 0x0f, 0xff],
+    [0x30, 0x62, 0x30, 0xff],
+    [0x0f, 0x38, 0x0f, 0xff],
+];
+
+const GRAYSCALE: Shades = [
+    [0xff, 0xff, 0xff, 0xff],
+    [0xaa, 0xaa, 0xaa, 0xff],
+    [0x55, 0x55, 0x55, 0xff],
+    [0x00, 0x00, 0x00, 0xff],
+];
+
+const POCKET: Shades = [
+    [0xc4, 0xcf, 0xa1, 0xff],
+    [0x8b, 0x95, 0x6d, 0xff],
+    [0x4d, 0x53, 0x3c, 0xff],
+    [0x1f, 0x1f, 0x1f, 0xff],
+];
+
+// Shade colors for the background and window and each of the two object palettes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub bg: Shades,
+    pub obj0: Shades,
+    pub obj1: Shades,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::uniform(GREEN)
+    }
+}
+
+impl Palette {
+    // Built-in palettes, selectable by name.
+    pub const PRESETS: [(&str, Palette); 3] = [
+        ("green", Self::uniform(GREEN)),
+        ("grayscale", Self::uniform(GRAYSCALE)),
+        ("pocket", Self::uniform(POCKET)),
+    ];
+
+    // Use the same shades for every layer.
+    pub const fn uniform(shades: Shades) -> Self {
+        Self { bg: shades, obj0: shades, obj1: shades }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        Self::PRESETS.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, p)| p)
+    }
+
+    // Load a palette from a text file. See parse() for the format.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Parse a palette from a list of colors written as RRGGBB hex values, optionally prefixed
+    // with '#' and separated by whitespace or commas, from lightest to darkest. Four colors are
+    // used for every layer; twelve give the background, object 0 and object 1 shades in turn.
+    // Anything after a ';' on a line is a comment.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let colors = text
+            .lines()
+            .map(|line| line.split(';').next().unwrap())
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                let hex = word.strip_prefix('#').unwrap_or(word);
+                match u32::from_str_radix(hex, 16) {
+                    Ok(rgb) if hex.len() == 6 => Ok([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 0xff]),
+                    _ => Err(format!("invalid color '{}'", word)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let shades = |i: usize| [colors[i], colors[i + 1], colors[i + 2], colors[i + 3]];
+        match colors.len() {
+            4 => Ok(Self::uniform(shades(0))),
+            12 => Ok(Self { bg: shades(0), obj0: shades(4), obj1: shades(8) }),
+            n => Err(format!("expected 4 or 12 colors, found {}", n)),
+        }
+    }
+}
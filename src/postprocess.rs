@@ -0,0 +1,109 @@
+// Optional processing of finished frames before they're shown, to imitate the look of a real
+// LCD: colors as the screen displays them, and the ghosting of its slow pixel response.
+
+use std::str::FromStr;
+
+// Color response of a handheld's LCD.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorCorrection {
+    #[default] None,
+    Cgb,    // Game Boy Color: darker and less saturated, with some colors bleeding into others
+    Gba,    // Game Boy Advance: like the CGB, but with a darker gamma
+}
+
+impl FromStr for ColorCorrection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(ColorCorrection::None),
+            "cgb" => Ok(ColorCorrection::Cgb),
+            "gba" => Ok(ColorCorrection::Gba),
+            _ => Err(format!("unknown color correction '{}'", s)),
+        }
+    }
+}
+
+impl ColorCorrection {
+    // The next mode, for cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            ColorCorrection::None => ColorCorrection::Cgb,
+            ColorCorrection::Cgb => ColorCorrection::Gba,
+            ColorCorrection::Gba => ColorCorrection::None,
+        }
+    }
+
+    // Weights of the input red, green and blue in each output channel, followed by the gamma of
+    // the screen (applied around the matrix) and its overall brightness.
+    fn params(self) -> Option<([[f32; 3]; 3], f32, f32)> {
+        match self {
+            ColorCorrection::None => None,
+            ColorCorrection::Cgb => Some(([
+                [26.0 / 32.0, 4.0 / 32.0, 2.0 / 32.0],
+                [0.0, 24.0 / 32.0, 8.0 / 32.0],
+                [6.0 / 32.0, 4.0 / 32.0, 22.0 / 32.0],
+            ], 1.0, 1.0)),
+            ColorCorrection::Gba => Some(([
+                [0.82, 0.125, 0.195],
+                [0.24, 0.665, 0.075],
+                [-0.06, 0.21, 0.73],
+            ], 2.2, 0.94)),
+        }
+    }
+
+    // Build a table of the corrected color for every RGB555 input.
+    fn table(self) -> Option<Vec<[u8; 3]>> {
+        let (matrix, gamma, brightness) = self.params()?;
+        let table = (0..0x8000u16).map(|rgb| {
+            let input = [rgb & 0x1F, (rgb >> 5) & 0x1F, (rgb >> 10) & 0x1F].map(|c| (c as f32 / 31.0).powf(gamma));
+            matrix.map(|row| {
+                let c = row.iter().zip(input).map(|(w, c)| w * c).sum::<f32>() * brightness;
+                (c.clamp(0.0, 1.0).powf(1.0 / gamma) * 255.0).round() as u8
+            })
+        });
+        Some(table.collect())
+    }
+}
+
+#[derive(Default)]
+pub struct PostProcess {
+    correction: ColorCorrection,
+    table: Option<Vec<[u8; 3]>>,    // Corrected color for each RGB555 value
+    persistence: f32,   // Fraction of the previous output kept in each new frame (0 for none)
+    prev: Vec<u8>,      // Previous output frame
+}
+
+impl PostProcess {
+    pub fn correction(&self) -> ColorCorrection {
+        self.correction
+    }
+
+    pub fn set_correction(&mut self, correction: ColorCorrection) {
+        self.correction = correction;
+        self.table = correction.table();
+    }
+
+    // Set how much of the previous frame remains visible, from 0 (none) to 1 (forever).
+    pub fn set_persistence(&mut self, persistence: f32) {
+        self.persistence = persistence.clamp(0.0, 1.0);
+    }
+
+    // Process an RGBA frame in place.
+    pub fn apply(&mut self, out: &mut [u8]) {
+        if let Some(table) = &self.table {
+            for pixel in out.chunks_exact_mut(4) {
+                let rgb = [pixel[0], pixel[1], pixel[2]].map(|c| (c >> 3) as usize);
+                pixel[..3].copy_from_slice(&table[rgb[0] | rgb[1] << 5 | rgb[2] << 10]);
+            }
+        }
+
+        if self.persistence > 0.0 && self.prev.len() == out.len() {
+            for (c, &prev) in out.iter_mut().zip(&self.prev) {
+                *c = (*c as f32 * (1.0 - self.persistence) + prev as f32 * self.persistence).round() as u8;
+            }
+        }
+        self.prev.clear();
+        self.prev.extend_from_slice(out);
+    }
+}
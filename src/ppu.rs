@@ -1,9 +1,29 @@
-use rand::{self, Rng};
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+use rand::Rng;
+
+use crate::bus::{BusDevice, Page, PAGE_SIZE};
+use crate::colorize::Colorization;
+use crate::frame::{FrameBuffer, Layer, Pixel};
+use crate::model::Model;
+
+mod fifo;
+pub mod inspect;
+
+use fifo::Fifo;
+use inspect::OamScan;
 
 pub const SCANLINES: usize = 154;
+pub const CYCLES_PER_SCANLINE: usize = 456;
+pub const CYCLES_PER_FRAME: usize = CYCLES_PER_SCANLINE * SCANLINES;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
-const SCREEN_WIDTH: usize = 160;
-const SCREEN_HEIGHT: usize = 144;
+const VRAM_BANK_SIZE: usize = 8 * 1024;
+const VRAM_SIZE: usize = 2 * VRAM_BANK_SIZE;
+const OAM_SIZE: usize = 160;
 
 const BG_WIDTH: usize = 256;
 const BG_HEIGHT: usize = 256;
@@ -33,18 +53,104 @@ const STAT_LCD: u8 = 0x03;
 const STAT_MODEMASK: u8 = 0x03;
 const STAT_RWMASK: u8 = 0x78;
 
+const IRQ_VBLANK: u8 = 0x01;
+const IRQ_STAT: u8 = 0x02;
+
+// On visible lines, mode 2 (OAM scan) lasts 80 dots. It's followed by mode 3 (drawing), which
+// takes at least 172 dots plus penalties for fine scrolling, the window and sprite fetches, and
+// then mode 0 (HBlank) for the rest of the line.
+const MODE2_END: usize = 80;
+const MODE3_MIN: usize = 172;
+const WINDOW_PENALTY: usize = 6;
+const SPRITE_PENALTY: usize = 6;
+
+const OBJ_BGPRIO: u8 = 0x80;
+const OBJ_YFLIP: u8 = 0x40;
+const OBJ_XFLIP: u8 = 0x20;
+const OBJ_PALETTE: u8 = 0x10;
+const OBJ_BANK: u8 = 0x08;      // Color mode only
+const OBJ_CGBPALETTE: u8 = 0x07; // Color mode only
+
+// BG map attributes (color mode only)
+const BG_PRIO: u8 = 0x80;
+const BG_YFLIP: u8 = 0x40;
+const BG_XFLIP: u8 = 0x20;
+const BG_BANK: u8 = 0x08;
+const BG_PALETTE: u8 = 0x07;
+
+// Palette index registers
+const PI_AUTOINC: u8 = 0x80;
+const PI_INDEXMASK: u8 = 0x3F;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// How scanlines are rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    // Draw each line in one go at the end of mode 3. Fast, but mid-line register changes are
+    // missed.
+    #[default]
+    Scanline,
+    // Emulate the pixel FIFOs dot by dot, so mid-line register changes show up where they happen.
+    Fifo,
+}
+
+// Layers left out of the picture, for debugging. They only change what's drawn, not the
+// registers or timing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LayerToggles {
+    pub hide_bg: bool,          // Draw the background as blank
+    pub hide_window: bool,      // Draw the background in place of the window
+    pub hide_sprites: bool,
+    pub force_bg_color0: bool,  // Draw the background and window with color 0 of their palettes
+}
+
+// A background or window pixel, with its BG map attributes in color mode.
+#[derive(Clone, Copy, Default)]
+struct BgPixel { color: u8, attrs: u8 }
+
+// A sprite pixel, with the sprite's attributes and OAM index.
+#[derive(Clone, Copy, Default)]
+struct ObjPixel { color: u8, attrs: u8, index: usize }
+
 // OAM sprite data
-struct Sprite { y: isize, x: isize, tile: u8, attrs: u8 }
+struct Sprite { index: usize, y: isize, x: isize, tile: u8, attrs: u8 }
 
 impl Sprite {
-    fn from(obj: &[u8]) -> Self {
+    fn from(index: usize, obj: &[u8]) -> Self {
         Self {
+            index,
             y: obj[0] as isize - 16,
             x: obj[1] as isize - 8,
             tile: obj[2],
             attrs: obj[3],
         }
     }
+
+    // Get the sprite's pixel at screen position (x,y), given the object height selected by LCDC.
+    // In 8x16 mode, the top tile is the sprite's tile number with the LSB cleared and the bottom
+    // tile is the one after it. In color mode, the tile can come from either VRAM bank.
+    fn pixel_at(&self, x: isize, y: isize, height: isize, vram: &[u8], cgb: bool) -> ObjPixel {
+        let mut spr_x = (x - self.x) as usize;
+        let mut spr_y = (y - self.y) as usize;
+        if self.attrs & OBJ_XFLIP != 0 {
+            spr_x = TILE_WIDTH - 1 - spr_x;
+        }
+        if self.attrs & OBJ_YFLIP != 0 {
+            spr_y = height as usize - 1 - spr_y;
+        }
+        let tile = if height == 16 {
+            (self.tile & 0xFE) | (spr_y / TILE_HEIGHT) as u8
+        } else {
+            self.tile
+        };
+        let bank = if cgb && self.attrs & OBJ_BANK != 0 { &vram[VRAM_BANK_SIZE..] } else { vram };
+        ObjPixel {
+            color: PPU::get_tile_pixel_color(tile, spr_x, spr_y % TILE_HEIGHT, bank, false),
+            attrs: self.attrs,
+            index: self.index,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -61,110 +167,428 @@ pub struct PPU {
     pub obp1: u8,   // Object palette 1 (non-color mode)
     pub bgpi: u8,   // Background palette index (color mode)
     pub obpi: u8,   // Object palette index (color mode)
+    pub vbk: u8,    // VRAM bank select (color mode)
+
+    pub layers: LayerToggles,
 
+    renderer: Renderer,         // Renderer picked by set_renderer()
+    line_renderer: Renderer,    // Renderer drawing the current line, updated when mode 3 starts
     stat: u8,       // LCDC status register
+    dot: usize,     // Current dot within the scanline
+    mode3_end: usize, // Dot at which mode 3 ends on the current line
+    hblank_start: bool, // Set on entering HBlank, cleared at the start of the next line
+    skip_frame: bool,   // Don't show the frame being drawn (the first one after turning the LCD on)
+    stat_line: bool, // STAT interrupt line, ORed together from all enabled sources
+    irq: u8,        // Pending interrupt requests (IF bits)
+    wly: u8,        // Window internal line counter
+    wy_hit: bool,   // Whether LY has matched WY this frame
     bgpd: Vec<u8>,  // Background palette data (color mode)
     obpd: Vec<u8>,  // Object palette data (color mode)
+
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    remap: bool,        // VRAM bank changed since the bus last asked for pages
+
+    fifo: Fifo,
+
+    framebuf: FrameBuffer,  // Last completed frame
+    backbuf: FrameBuffer,   // Frame currently being drawn
+    oam_scans: Vec<OamScan>,        // Results of the OAM scan on each line of the last frame
+    oam_scans_back: Vec<OamScan>,   // Same for the frame being drawn
+
+    model: Model,
+    cgb_mode: bool,     // Rendering with color features (false for DMG games on a CGB)
 }
 
 impl PPU {
-    pub fn new() -> Self {
-        let mut ppu = Self::default();
+    // Create a PPU, filling the memory that starts out as junk from rng.
+    pub fn new(model: Model, rng: &mut impl Rng) -> Self {
+        let mut ppu = Self { model, cgb_mode: model.is_cgb(), ..Self::default() };
         // Background palette is initialized to all white on startup, but object palette is left as
         // random junk.
         for _ in 0..64 {
             ppu.bgpd.push(0xff);
-            ppu.obpd.push(rand::rng().random());
+            ppu.obpd.push(rng.random());
         }
+        ppu.vram = (0..VRAM_SIZE).map(|_| rng.random()).collect();
+        ppu.oam = (0..OAM_SIZE).map(|_| rng.random()).collect();
         ppu.lcdc = LCDC_ON | LCDC_BG8000 | LCDC_BGON;
         ppu.bgp = 0xFC;
         ppu.obp0 = 0xFF;
         ppu.obp1 = 0xFF;
+        ppu.start_line(0);
         ppu
     }
 
-    // Get the color index of pixel (x,y) of the given tile. If select is false, use "0x8000"
-    // addressing into VRAM tile data, and if select is true, use "0x8800" addressing.
-    fn get_tile_pixel_color(tile: u8, x: usize, y: usize, vram: &[u8], select: bool) -> u8 {
-        assert!(x < 8);
-        assert!(y < 8);
-        let index = if select {
+    // Get the last completed frame.
+    pub fn frame(&self) -> &FrameBuffer {
+        &self.framebuf
+    }
+
+    // Get the renderer picked by set_renderer().
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    // Pick the renderer. It takes over when mode 3 next starts, so a line is never started by
+    // one renderer and finished by the other.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    // Get the position of a tile among the 384 in a VRAM bank. If select is false, use "0x8000"
+    // addressing, and if select is true, use "0x8800" addressing.
+    fn tile_data_index(tile: u8, select: bool) -> usize {
+        if select {
             if tile < 128 { tile as usize + 256 } else { tile as usize + 128 }
         } else {
             tile as usize
-        };
+        }
+    }
+
+    // Get the color index of pixel (x,y) of the given tile, addressed as by tile_data_index().
+    fn get_tile_pixel_color(tile: u8, x: usize, y: usize, vram: &[u8], select: bool) -> u8 {
+        assert!(x < 8);
+        assert!(y < 8);
+        let index = Self::tile_data_index(tile, select);
         let (byte0, byte1) = (vram[index*16 + y*2], vram[index*16 + y*2 + 1]);
         let (bit0, bit1) = ((byte0 >> (7-x)) & 0x1, (byte1 >> (7-x)) & 0x1);
         bit0 | (bit1 << 1)
     }
 
-    fn put_color(pixel: &mut [u8], color: u8, palette: u8) {
-        let rgba = match (palette >> (color*2)) & 0x3 {
-            0b00 => [0x9b, 0xbc, 0x0f, 0xff],
-            0b01 => [0x8b, 0xac, 0x0f, 0xff],
-            0b10 => [0x30, 0x62, 0x30, 0xff],
-            0b11 => [0x0f, 0x38, 0x0f, 0xff],
-            _ => unreachable!()
-        };
-        pixel.copy_from_slice(&rgba);
+    // Switch a CGB into DMG compatibility mode, for cartridges without color support. The
+    // shades picked by the DMG palette registers index into the given color palettes, which are
+    // loaded into BG palette 0 and OBJ palettes 0 and 1.
+    pub fn set_dmg_compatibility(&mut self, colors: &Colorization) {
+        self.cgb_mode = false;
+        for (bytes, color) in self.bgpd.chunks_exact_mut(2).zip(colors.bg) {
+            bytes.copy_from_slice(&color.to_le_bytes());
+        }
+        for (bytes, color) in self.obpd.chunks_exact_mut(2).zip(colors.obj0.iter().chain(&colors.obj1)) {
+            bytes.copy_from_slice(&color.to_le_bytes());
+        }
     }
 
-    pub fn draw_scanline(&mut self, framebuf: &mut [u8], vram: &[u8], oam: &[u8]) {
-        let y = self.ly as usize;
-        self.ly = (self.ly + 1) % SCANLINES as u8;
+    // Get the shade that `color` maps to through a DMG palette register. A CGB looks the shade
+    // up in color palette `cgb_palette` of the given palette data instead.
+    fn shade(&self, color: u8, palette: u8, layer: Layer, palette_data: &[u8], cgb_palette: u8) -> Pixel {
+        let shade = (palette >> (color*2)) & 0x3;
+        if self.model.is_cgb() {
+            Self::rgb555(palette_data, cgb_palette, shade)
+        } else {
+            Pixel::Shade(layer, shade)
+        }
+    }
+
+    // Get color `color` of the given color mode palette, stored as little-endian RGB555.
+    fn rgb555(palette_data: &[u8], palette: u8, color: u8) -> Pixel {
+        let i = (palette as usize * 4 + color as usize) * 2;
+        Pixel::Rgb555(palette_data[i] as u16 | (palette_data[i + 1] as u16) << 8)
+    }
+
+    // Combine the background/window and sprite pixels at a spot on the screen into the resulting
+    // color.
+    fn mix_pixel(&self, bg: BgPixel, obj: ObjPixel) -> Pixel {
+        let obj_visible = obj.color != 0 && self.lcdc & LCDC_OBJON != 0;
+        if self.cgb_mode {
+            // LCDC bit 0 is the master priority switch in color mode. When it's clear, sprites
+            // are always drawn on top. Otherwise, either the BG map or the sprite attributes can
+            // put background colors 1-3 in front.
+            let obj_wins = obj_visible && (self.lcdc & LCDC_BGON == 0
+                || bg.color == 0
+                || (bg.attrs & BG_PRIO == 0 && obj.attrs & OBJ_BGPRIO == 0));
+            return if obj_wins {
+                Self::rgb555(&self.obpd, obj.attrs & OBJ_CGBPALETTE, obj.color)
+            } else {
+                Self::rgb555(&self.bgpd, bg.attrs & BG_PALETTE, bg.color)
+            };
+        }
+
+        // With LCDC bit 0 clear, the background and window are blank (white), and count as color
+        // 0 for sprite priority. Sprites with the BG priority attribute are hidden behind
+        // background colors 1-3.
+        let (bg_color, bg_palette) = if self.lcdc & LCDC_BGON == 0 { (0, 0) } else { (bg.color, self.bgp) };
+        if obj_visible && (obj.attrs & OBJ_BGPRIO == 0 || bg_color == 0) {
+            if obj.attrs & OBJ_PALETTE == 0 {
+                self.shade(obj.color, self.obp0, Layer::Obj0, &self.obpd, 0)
+            } else {
+                self.shade(obj.color, self.obp1, Layer::Obj1, &self.obpd, 1)
+            }
+        } else {
+            self.shade(bg_color, bg_palette, Layer::Bg, &self.bgpd, 0)
+        }
+    }
+
+    // Get the background or window pixel at (map_x,map_y) of the tile map at the given VRAM
+    // offset. In color mode, the attributes in VRAM bank 1 pick the tile's bank and palette, and
+    // can flip it.
+    fn bg_pixel(&self, tilemap: usize, map_x: usize, map_y: usize) -> BgPixel {
+        let tilemap_index = tilemap + (map_y / TILE_HEIGHT) * TILEMAP_WIDTH + map_x / TILE_WIDTH;
+        let tile = self.vram[tilemap_index];
+        let attrs = if self.cgb_mode { self.vram[VRAM_BANK_SIZE + tilemap_index] } else { 0 };
+
+        let mut tile_x = map_x % TILE_WIDTH;
+        let mut tile_y = map_y % TILE_HEIGHT;
+        if attrs & BG_XFLIP != 0 {
+            tile_x = TILE_WIDTH - 1 - tile_x;
+        }
+        if attrs & BG_YFLIP != 0 {
+            tile_y = TILE_HEIGHT - 1 - tile_y;
+        }
+        let bank = if attrs & BG_BANK != 0 { &self.vram[VRAM_BANK_SIZE..] } else { &self.vram[..] };
+        let bg_select = self.lcdc & LCDC_BG8000 == 0;
+        BgPixel {
+            color: Self::get_tile_pixel_color(tile, tile_x, tile_y, bank, bg_select),
+            attrs,
+        }
+    }
+
+    // Get the background pixel at screen position (x,LY).
+    fn background_pixel(&self, x: usize) -> BgPixel {
+        let bg_map = if self.lcdc & LCDC_BG9C00 != 0 { 0x1C00 } else { 0x1800 };
+        let map_x = (x + self.scx as usize) % BG_WIDTH;
+        let map_y = (self.ly as usize + self.scy as usize) % BG_HEIGHT;
+        self.bg_pixel(bg_map, map_x, map_y)
+    }
+
+    // Apply the layer toggles to the pixels at screen position (x,LY) before they're mixed.
+    fn toggle_layers(&self, x: usize, mut bg: BgPixel, in_window: bool, mut obj: ObjPixel) -> (BgPixel, ObjPixel) {
+        let window_shown = in_window && !self.layers.hide_window;
+        if in_window && !window_shown {
+            bg = self.background_pixel(x);
+        }
+        if !window_shown && self.layers.hide_bg {
+            bg = BgPixel::default();
+        }
+        if self.layers.force_bg_color0 {
+            bg.color = 0;
+        }
+        if self.layers.hide_sprites {
+            obj = ObjPixel::default();
+        }
+        (bg, obj)
+    }
+
+    // Set the mode bits of STAT.
+    fn set_mode(&mut self, mode: u8) {
+        self.stat = (self.stat & !STAT_MODEMASK) | mode;
+        self.update_stat_line();
+    }
+
+    // Recompute the STAT interrupt line. The interrupt is only requested on a rising edge, so one
+    // source being active blocks the others from raising another interrupt until it goes low.
+    fn update_stat_line(&mut self) {
         if self.ly == self.lyc {
             self.stat |= STAT_LYC;
         } else {
             self.stat &= !STAT_LYC;
         }
 
-        if y >= SCREEN_HEIGHT {
-            return;
+        let mode = self.stat & STAT_MODEMASK;
+        let line = (self.stat & STAT_ILYC != 0 && self.stat & STAT_LYC != 0)
+            || (self.stat & STAT_IHBL != 0 && mode == STAT_HBL)
+            || (self.stat & STAT_IVBL != 0 && mode == STAT_VBL)
+            || (self.stat & STAT_IOAM != 0 && mode == STAT_OAM);
+        if line && !self.stat_line {
+            self.irq |= IRQ_STAT;
         }
+        self.stat_line = line;
+    }
 
-        let mut sprites_this_line: Vec<Sprite> = oam
-            .chunks_exact(4)
-            .map(|obj| Sprite::from(obj))
-            .filter(|s| (y as isize) >= s.y && (y as isize) < s.y + (TILE_HEIGHT as isize))
-            .take(10)
-            .collect();
-        sprites_this_line.sort_by(|s1, s2| s1.x.cmp(&s2.x)); // NOTE: don't do this in CGB mode
+    // Take the interrupts requested by the PPU since the last call, as IF bits.
+    pub fn take_interrupts(&mut self) -> u8 {
+        std::mem::take(&mut self.irq)
+    }
 
-        let scanline_start = y * SCREEN_WIDTH * 4;
-        let scanline = &mut framebuf[scanline_start..scanline_start+SCREEN_WIDTH*4];
+    pub fn set_lcdc(&mut self, lcdc: u8) {
+        let was_on = self.lcdc & LCDC_ON != 0;
+        self.lcdc = lcdc;
+        if was_on && lcdc & LCDC_ON == 0 {
+            // With the LCD off, LY stays at 0, the mode stays at 0 and the screen is blank.
+            self.ly = 0;
+            self.dot = 0;
+            self.stat &= !STAT_MODEMASK;
+            self.stat_line = false;
+            self.hblank_start = false;
+            self.blank_frame();
+        } else if !was_on && lcdc & LCDC_ON != 0 {
+            // The LCD doesn't show anything until the first full frame after turning on is done.
+            self.skip_frame = true;
+            self.start_line(0);
+        }
+    }
 
-        for (x, pixel) in scanline.chunks_exact_mut(4).enumerate() {
-            let tilemap_x = ((x + self.scx as usize) % BG_WIDTH) / TILE_WIDTH;
-            let tilemap_y = ((y + self.scy as usize) % BG_HEIGHT) / TILE_HEIGHT;
+    // Show a blank (white) screen.
+    fn blank_frame(&mut self) {
+        let white = if self.model.is_cgb() { Pixel::Rgb555(0x7FFF) } else { Pixel::Shade(Layer::Bg, 0) };
+        self.framebuf.fill(white);
+    }
 
-            let tilemap_index = tilemap_y * TILEMAP_WIDTH + tilemap_x;
-            let tile = vram[0x1800 + tilemap_index];
+    // Take whether HBlank has started on the current line since the last call.
+    pub fn take_hblank_start(&mut self) -> bool {
+        std::mem::take(&mut self.hblank_start)
+    }
 
-            let tile_x = (x + self.scx as usize) % TILE_WIDTH;
-            let tile_y = (y + self.scy as usize) % TILE_HEIGHT;
+    // Dot at which the current mode ends, or the line ends during HBlank and VBlank.
+    fn next_event(&self) -> usize {
+        match self.stat & STAT_MODEMASK {
+            STAT_OAM => MODE2_END,
+            STAT_LCD => self.mode3_end,
+            _ => CYCLES_PER_SCANLINE,
+        }
+    }
 
-            let bg_select = self.lcdc & LCDC_BG8000 == 0;
-            let bg_color = Self::get_tile_pixel_color(tile, tile_x, tile_y, vram, bg_select);
-            let bg_palette = if self.lcdc & LCDC_BGON == 0 { 0 } else { self.bgp };
+    // Move on to the next mode once the current one has ended.
+    fn advance(&mut self) {
+        match self.stat & STAT_MODEMASK {
+            STAT_OAM => {
+                self.record_oam_scan();
+                self.line_renderer = self.renderer;
+                match self.line_renderer {
+                    Renderer::Scanline => self.mode3_end = MODE2_END + self.mode3_length(),
+                    Renderer::Fifo => self.start_fifo(),
+                }
+                self.set_mode(STAT_LCD);
+            },
+            STAT_LCD => {
+                self.draw_scanline();
+                self.start_hblank();
+            },
+            _ => self.start_line((self.ly + 1) % SCANLINES as u8),
+        }
+    }
 
-            let sprite = sprites_this_line
-                .iter()
-                .find(|&s| (x as isize) >= s.x && (x as isize) < s.x + (TILE_WIDTH as isize));
+    fn start_hblank(&mut self) {
+        self.hblank_start = true;
+        self.set_mode(STAT_HBL);
+    }
 
-            if let Some(sprite) = sprite {
-                let spr_x = (x as isize - sprite.x) as usize;
-                let spr_y = (y as isize - sprite.y) as usize;
-                let spr_color = Self::get_tile_pixel_color(sprite.tile, spr_x, spr_y, vram, false);
-                let spr_palette = if sprite.attrs & 0x10 == 0 { self.obp0 } else { self.obp1 };
+    fn start_line(&mut self, ly: u8) {
+        self.ly = ly;
+        self.dot = 0;
+        self.hblank_start = false;
 
-                if spr_color == 0 || self.lcdc & LCDC_OBJON == 0 {
-                    Self::put_color(pixel, bg_color, bg_palette);
+        if ly as usize >= SCREEN_HEIGHT {
+            if ly as usize == SCREEN_HEIGHT {
+                self.irq |= IRQ_VBLANK;
+                if self.skip_frame {
+                    self.skip_frame = false;
                 } else {
-                    Self::put_color(pixel, spr_color, spr_palette);
+                    std::mem::swap(&mut self.framebuf, &mut self.backbuf);
                 }
-            } else {
-                Self::put_color(pixel, bg_color, bg_palette);
+                std::mem::swap(&mut self.oam_scans, &mut self.oam_scans_back);
             }
+            self.set_mode(STAT_VBL);
+            return;
+        }
+
+        // The window can only appear once LY has matched WY during the frame. Its line counter
+        // only advances on lines where it's actually drawn.
+        if ly == 0 {
+            self.wly = 0;
+            self.wy_hit = false;
+            self.oam_scans_back.clear();
+        }
+        if ly == self.wy {
+            self.wy_hit = true;
+        }
+        self.set_mode(STAT_OAM);
+    }
+
+    fn window_visible(&self) -> bool {
+        self.lcdc & LCDC_WINON != 0 && self.wy_hit && self.wx < 167
+    }
+
+    fn obj_height(&self) -> isize {
+        if self.lcdc & LCDC_OBJ16 != 0 { 16 } else { 8 }
+    }
+
+    // Get the sprites that overlap line y, in OAM order.
+    fn sprites_overlapping(&self, y: usize) -> impl Iterator<Item = Sprite> + '_ {
+        let obj_height = self.obj_height();
+        self.oam
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(index, obj)| Sprite::from(index, obj))
+            .filter(move |s| (y as isize) >= s.y && (y as isize) < s.y + obj_height)
+    }
+
+    // Get the sprites drawn on line y in priority order. The first 10 sprites in OAM order that
+    // overlap the line are drawn. Among those, the one with the lowest X coordinate has priority,
+    // with ties going to the one earlier in OAM (the sort is stable). In color mode, priority
+    // only goes by OAM order.
+    fn sprites_on_line(&self, y: usize) -> Vec<Sprite> {
+        if self.lcdc & LCDC_OBJON == 0 {
+            return Vec::new();
+        }
+        let mut sprites: Vec<Sprite> = self.sprites_overlapping(y)
+            .take(MAX_SPRITES_PER_LINE)
+            .collect();
+        if !self.cgb_mode {
+            sprites.sort_by_key(|s| s.x);
+        }
+        sprites
+    }
+
+    // Number of dots mode 3 takes on the current line. The fetcher discards SCX % 8 pixels at the
+    // start of the line, restarts when it reaches the window, and stalls for every sprite. A
+    // sprite stalls it for 6 dots, plus up to 5 more if it has to wait for the BG tile the sprite
+    // starts in to finish fetching, which only happens for the first sprite in each tile.
+    fn mode3_length(&self) -> usize {
+        let mut length = MODE3_MIN + (self.scx % 8) as usize;
+        if self.window_visible() {
+            length += WINDOW_PENALTY;
+        }
+
+        let mut fetched_tiles = Vec::new();
+        for sprite in self.sprites_on_line(self.ly as usize) {
+            length += SPRITE_PENALTY;
+            let bg_x = sprite.x + 8 + (self.scx % 8) as isize;
+            let tile = bg_x.div_euclid(TILE_WIDTH as isize);
+            if !fetched_tiles.contains(&tile) {
+                fetched_tiles.push(tile);
+                length += 5usize.saturating_sub(bg_x.rem_euclid(TILE_WIDTH as isize) as usize);
+            }
+        }
+        length
+    }
+
+    // Render the current line into the back buffer.
+    fn draw_scanline(&mut self) {
+        let y = self.ly as usize;
+        if y >= SCREEN_HEIGHT {
+            return;
+        }
+        let window_visible = self.window_visible();
+        let window_map = if self.lcdc & LCDC_WIN9C00 != 0 { 0x1C00 } else { 0x1800 };
+        let obj_height = self.obj_height();
+        let sprites_this_line = self.sprites_on_line(y);
+
+        for x in 0..SCREEN_WIDTH {
+            // The window starts at screen column WX-7. With WX below 7, its leftmost columns are
+            // cut off instead.
+            let in_window = window_visible && x + 7 >= self.wx as usize;
+            let bg = if in_window {
+                self.bg_pixel(window_map, x + 7 - self.wx as usize, self.wly as usize)
+            } else {
+                self.background_pixel(x)
+            };
+
+            // A transparent sprite pixel lets the next sprite in priority order show through.
+            let obj = sprites_this_line
+                .iter()
+                .filter(|&s| (x as isize) >= s.x && (x as isize) < s.x + (TILE_WIDTH as isize))
+                .map(|s| s.pixel_at(x as isize, y as isize, obj_height, &self.vram, self.cgb_mode))
+                .find(|p| p.color != 0)
+                .unwrap_or_default();
+
+            let (bg, obj) = self.toggle_layers(x, bg, in_window, obj);
+            let pixel = self.mix_pixel(bg, obj);
+            self.backbuf.set(x, y, pixel);
+        }
+
+        if window_visible {
+            self.wly += 1;
         }
     }
 
@@ -174,21 +598,168 @@ impl PPU {
 
     pub fn set_stat(&mut self, stat: u8) {
         self.stat = (self.stat & !STAT_RWMASK) | (stat & STAT_RWMASK);
+        self.update_stat_line();
+    }
+
+    // Palette RAM can't be accessed while the PPU is drawing.
+    fn palette_locked(&self) -> bool {
+        self.lcdc & LCDC_ON != 0 && self.stat & STAT_MODEMASK == STAT_LCD
+    }
+
+    // Advance a palette index register after a data write, if auto-increment is enabled.
+    fn increment_palette_index(index: &mut u8) {
+        if *index & PI_AUTOINC != 0 {
+            *index = PI_AUTOINC | ((*index & PI_INDEXMASK) + 1) & PI_INDEXMASK;
+        }
     }
 
     pub fn get_bgpd(&self) -> u8 {
-        self.bgpd[(self.bgpi & 0x3f) as usize]
+        if self.palette_locked() {
+            return 0xFF;
+        }
+        self.bgpd[(self.bgpi & PI_INDEXMASK) as usize]
     }
 
     pub fn set_bgpd(&mut self, value: u8) {
-         self.bgpd[(self.bgpi & 0x3f) as usize] = value;  
+        if !self.palette_locked() {
+            self.bgpd[(self.bgpi & PI_INDEXMASK) as usize] = value;
+        }
+        Self::increment_palette_index(&mut self.bgpi);
     }
 
     pub fn get_obpd(&self) -> u8 {
-        self.obpd[(self.obpi & 0x3f) as usize]
+        if self.palette_locked() {
+            return 0xFF;
+        }
+        self.obpd[(self.obpi & PI_INDEXMASK) as usize]
     }
 
     pub fn set_obpd(&mut self, value: u8) {
-         self.obpd[(self.obpi & 0x3f) as usize] = value;
+        if !self.palette_locked() {
+            self.obpd[(self.obpi & PI_INDEXMASK) as usize] = value;
+        }
+        Self::increment_palette_index(&mut self.obpi);
+    }
+
+    // Offset into VRAM of the bank the CPU currently sees.
+    fn vram_bank_offset(&self) -> usize {
+        (self.vbk & 0x1) as usize * VRAM_BANK_SIZE
+    }
+}
+
+impl BusDevice for PPU {
+    fn ranges(&self) -> Vec<RangeInclusive<u16>> {
+        let mut ranges = vec![0x8000..=0x9FFF, 0xFE00..=0xFEFF, 0xFF40..=0xFF45, 0xFF47..=0xFF4B];
+        if self.model.is_cgb() {
+            ranges.push(0xFF4F..=0xFF4F);
+            ranges.push(0xFF68..=0xFF6B);
+        }
+        ranges
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x8000..0xA000 => self.vram[self.vram_bank_offset() + addr as usize - 0x8000],
+            0xFE00..0xFEA0 => self.oam[addr as usize - 0xFE00],
+            0xFEA0..0xFF00 => 0x00,
+            0xFF40 => self.lcdc,
+            0xFF41 => self.get_stat(),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            0xFF4F => self.vbk | 0xFE,
+            0xFF68 => self.bgpi | 0x40,
+            0xFF69 => self.get_bgpd(),
+            0xFF6A => self.obpi | 0x40,
+            0xFF6B => self.get_obpd(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..0xA000 => {
+                let offset = self.vram_bank_offset();
+                self.vram[offset + addr as usize - 0x8000] = data;
+            },
+            0xFE00..0xFEA0 => self.oam[addr as usize - 0xFE00] = data,
+            0xFF40 => self.set_lcdc(data),
+            0xFF41 => self.set_stat(data),
+            0xFF42 => self.scy = data,
+            0xFF43 => self.scx = data,
+            // LY is read only.
+            0xFF44 => (),
+            0xFF45 => {
+                self.lyc = data;
+                self.update_stat_line();
+            },
+            0xFF47 => self.bgp = data,
+            0xFF48 => self.obp0 = data,
+            0xFF49 => self.obp1 = data,
+            0xFF4A => self.wy = data,
+            0xFF4B => self.wx = data,
+            0xFF4F => {
+                self.vbk = data & 0x1;
+                self.remap = true;
+            },
+            0xFF68 => self.bgpi = data & (PI_AUTOINC | PI_INDEXMASK),
+            0xFF69 => self.set_bgpd(data),
+            0xFF6A => self.obpi = data & (PI_AUTOINC | PI_INDEXMASK),
+            0xFF6B => self.set_obpd(data),
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, mut cycles: usize) -> u8 {
+        if self.lcdc & LCDC_ON == 0 {
+            return 0;
+        }
+        while cycles > 0 {
+            // In FIFO mode, the length of mode 3 comes out of emulating it dot by dot.
+            if self.line_renderer == Renderer::Fifo && self.stat & STAT_MODEMASK == STAT_LCD {
+                self.dot += 1;
+                cycles -= 1;
+                if self.fifo_step() {
+                    self.start_hblank();
+                }
+                continue;
+            }
+
+            let next = self.next_event();
+            let step = cycles.min(next - self.dot);
+            self.dot += step;
+            cycles -= step;
+            if self.dot == next {
+                self.advance();
+            }
+        }
+        self.take_interrupts()
+    }
+
+    unsafe fn pages(&mut self) -> Vec<(u8, Page)> {
+        let offset = self.vram_bank_offset();
+        self.vram[offset..offset+VRAM_BANK_SIZE]
+            .chunks_exact_mut(PAGE_SIZE)
+            .enumerate()
+            .map(|(i, page)| (0x80 + i as u8, Page::ReadWrite(page.as_mut_ptr())))
+            .collect()
+    }
+
+    fn take_remap(&mut self) -> bool {
+        std::mem::take(&mut self.remap)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
     }
 }
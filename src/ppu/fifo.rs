@@ -0,0 +1,146 @@
+// Pixel FIFO renderer. Instead of drawing a whole line at the end of mode 3, this emulates the
+// background fetcher and the BG and OBJ pixel FIFOs one dot at a time, so register and OAM writes
+// made partway through a line change the pixels shifted out after them. The PPU only catches up
+// with the CPU once each instruction is done, though, so a write already applies to the dots of
+// the instruction that made it: it can show up to a few pixels early, rather than at the exact
+// pixel as on hardware.
+
+use std::collections::VecDeque;
+
+use super::*;
+
+// Dots the fetcher takes to read a tile number and its two bytes of pixel data.
+const FETCH_DOTS: usize = 6;
+
+#[derive(Default)]
+pub(super) struct Fifo {
+    bg: VecDeque<BgPixel>,      // Background/window pixels
+    obj: VecDeque<ObjPixel>,    // Sprite pixels, lined up with the front of the BG FIFO
+    sprites: Vec<Sprite>,       // Sprites on this line not fetched yet, in priority order
+    obj_height: isize,          // Object height the sprites were picked with
+    row: Option<[BgPixel; 8]>,  // Tile row fetched but not yet pushed to the BG FIFO
+    fetch_x: usize,             // Tile column of the next fetch, relative to the line or window
+    fetch_dots: usize,          // Dots spent so far on the current fetch
+    stall: usize,               // Dots left in the current sprite fetch
+    discard: usize,             // Pixels left to drop for fine scrolling
+    lx: usize,                  // Next screen column to output
+    in_window: bool,            // Whether the fetcher has switched to the window
+}
+
+impl PPU {
+    // Set up the FIFOs and fetcher for the start of mode 3.
+    pub(super) fn start_fifo(&mut self) {
+        self.fifo = Fifo {
+            sprites: self.sprites_on_line(self.ly as usize),
+            obj_height: self.obj_height(),
+            discard: (self.scx % 8) as usize,
+            ..Fifo::default()
+        };
+    }
+
+    // Advance the FIFO renderer by one dot. Returns true once all 160 pixels of the line are out.
+    pub(super) fn fifo_step(&mut self) -> bool {
+        // Reaching the window throws away the background pixels and restarts the fetcher on the
+        // window's tile map. With WX below 7, the window's leftmost pixels are discarded instead.
+        if !self.fifo.in_window && self.window_visible() && self.fifo.lx + 7 >= self.wx as usize {
+            self.fifo.in_window = true;
+            self.fifo.bg.clear();
+            self.fifo.row = None;
+            self.fifo.fetch_x = 0;
+            self.fifo.fetch_dots = 0;
+            self.fifo.discard = 7usize.saturating_sub(self.wx as usize);
+        }
+
+        // A sprite fetch pauses the fetcher and pixel output until it's done.
+        if self.fifo.stall > 0 {
+            self.fifo.stall -= 1;
+            if self.fifo.stall == 0 {
+                self.fifo_merge_sprite();
+            }
+            return false;
+        }
+        let sprite_here = self.fifo.sprites.iter().any(|s| s.x <= self.fifo.lx as isize);
+        if sprite_here && self.fifo.discard == 0 && !self.fifo.bg.is_empty() {
+            self.fifo.stall = SPRITE_PENALTY;
+            return false;
+        }
+
+        if self.fifo.row.is_none() {
+            self.fifo.fetch_dots += 1;
+            if self.fifo.fetch_dots == FETCH_DOTS {
+                self.fifo.row = Some(self.fifo_fetch_row());
+                self.fifo.fetch_x += 1;
+                self.fifo.fetch_dots = 0;
+            }
+        }
+        if self.fifo.bg.is_empty() && let Some(row) = self.fifo.row.take() {
+            self.fifo.bg.extend(row);
+        }
+
+        if let Some(bg) = self.fifo.bg.pop_front() {
+            let obj = self.fifo.obj.pop_front().unwrap_or_default();
+            if self.fifo.discard > 0 {
+                self.fifo.discard -= 1;
+                return false;
+            }
+            let (bg, obj) = self.toggle_layers(self.fifo.lx, bg, self.fifo.in_window, obj);
+            let pixel = self.mix_pixel(bg, obj);
+            if (self.ly as usize) < SCREEN_HEIGHT {
+                self.backbuf.set(self.fifo.lx, self.ly as usize, pixel);
+            }
+            self.fifo.lx += 1;
+        }
+
+        if self.fifo.lx == SCREEN_WIDTH {
+            if self.fifo.in_window {
+                self.wly += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    // Fetch the next row of 8 background or window pixels, using the registers as they are now.
+    fn fifo_fetch_row(&self) -> [BgPixel; 8] {
+        let (tilemap, map_x, map_y) = if self.fifo.in_window {
+            let window_map = if self.lcdc & LCDC_WIN9C00 != 0 { 0x1C00 } else { 0x1800 };
+            (window_map, self.fifo.fetch_x * TILE_WIDTH, self.wly as usize)
+        } else {
+            let bg_map = if self.lcdc & LCDC_BG9C00 != 0 { 0x1C00 } else { 0x1800 };
+            let map_x = (self.scx as usize & !(TILE_WIDTH - 1)) + self.fifo.fetch_x * TILE_WIDTH;
+            (bg_map, map_x % BG_WIDTH, (self.ly as usize + self.scy as usize) % BG_HEIGHT)
+        };
+
+        let mut row = [BgPixel::default(); 8];
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = self.bg_pixel(tilemap, (map_x + x) % BG_WIDTH, map_y);
+        }
+        row
+    }
+
+    // Mix the next sprite that starts at or before the current column into the OBJ FIFO. Pixels
+    // already in the FIFO belong to sprites with higher priority and are kept, unless they're
+    // transparent or, in color mode, come from a sprite later in OAM.
+    fn fifo_merge_sprite(&mut self) {
+        let lx = self.fifo.lx as isize;
+        let Some(next) = self.fifo.sprites.iter().position(|s| s.x <= lx) else {
+            return;
+        };
+        // The sprite's rows are fetched at the height it was picked with, even if LCDC has
+        // changed it since.
+        let sprite = self.fifo.sprites.remove(next);
+        let obj_height = self.fifo.obj_height;
+        for x in sprite.x.max(lx)..sprite.x + TILE_WIDTH as isize {
+            let slot = (x - lx) as usize;
+            if self.fifo.obj.len() <= slot {
+                self.fifo.obj.resize(slot + 1, ObjPixel::default());
+            }
+            let old = self.fifo.obj[slot];
+            let new = sprite.pixel_at(x, self.ly as isize, obj_height, &self.vram, self.cgb_mode);
+            let replace = old.color == 0 || (self.cgb_mode && new.color != 0 && new.index < old.index);
+            if replace {
+                self.fifo.obj[slot] = new;
+            }
+        }
+    }
+}
@@ -0,0 +1,309 @@
+// Debugging views of VRAM and OAM, drawn with the current palettes: every tile in tile data, both
+// tile maps, and the sprites with the lines they were drawn on.
+
+use std::fmt;
+
+use super::*;
+use crate::frame::Image;
+
+const TILES_PER_BANK: usize = 384;
+const TILE_VIEW_COLUMNS: usize = 16;
+const TILEMAP_SIZE: usize = TILEMAP_WIDTH * TILEMAP_WIDTH;
+
+// Outline of the area of the background shown on screen.
+const VIEWPORT_COLOR: Pixel = Pixel::Rgb555(0x001F);
+
+// The sprite view has a column per OAM entry, with the sprite at the top and a chart of the lines
+// it was on in the last frame below.
+const SPRITE_COLUMN_WIDTH: usize = TILE_WIDTH + 2;
+const SPRITE_CHART_TOP: usize = 2 * TILE_HEIGHT + 2;
+const SPRITE_COUNT: usize = OAM_SIZE / 4;
+const GAP_COLOR: Pixel = Pixel::Rgb555(0x0000);
+const UNUSED_COLOR: Pixel = Pixel::Rgb555(0x1084);
+const DRAWN_COLOR: Pixel = Pixel::Rgb555(0x03E0);
+const DROPPED_COLOR: Pixel = Pixel::Rgb555(0x001F);
+
+// Which OAM entries the OAM scan found on a line, as bit masks indexed by entry.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct OamScan {
+    line: usize,
+    drawn: u64,     // Among the first 10
+    dropped: u64,   // Left out by the 10 sprite limit
+}
+
+// The tile under a spot in one of the views.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileInfo {
+    pub map_address: Option<u16>,   // Tile map entry the tile was picked by, in the tile map view
+    pub tile: u8,       // Tile number, as used in tile maps and OAM
+    pub index: usize,   // Position among the 384 tiles of its bank
+    pub bank: usize,    // VRAM bank
+    pub address: u16,   // Address of the tile data
+}
+
+impl fmt::Display for TileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(map_address) = self.map_address {
+            write!(f, "map {:04X}: ", map_address)?;
+        }
+        write!(f, "tile {:02X} (#{}) at {}:{:04X}", self.tile, self.index, self.bank, self.address)
+    }
+}
+
+impl PPU {
+    // Number of VRAM banks (two in color models, even when running a DMG game).
+    fn vram_banks(&self) -> usize {
+        if self.model.is_cgb() { 2 } else { 1 }
+    }
+
+    // Color of a BG color index in a view. Color mode uses the BG palette picked by the
+    // attributes, otherwise the shade comes from BGP.
+    fn view_color(&self, color: u8, attrs: u8) -> Pixel {
+        if self.cgb_mode {
+            Self::rgb555(&self.bgpd, attrs & BG_PALETTE, color)
+        } else {
+            self.shade(color, self.bgp, Layer::Bg, &self.bgpd, 0)
+        }
+    }
+
+    // Draw all 384 tiles of each VRAM bank in rows of 16, with the banks side by side.
+    pub fn tile_view(&self) -> Image {
+        let bank_width = TILE_VIEW_COLUMNS * TILE_WIDTH;
+        let rows = TILES_PER_BANK / TILE_VIEW_COLUMNS;
+        let mut image = Image::new(bank_width * self.vram_banks(), rows * TILE_HEIGHT);
+        for bank in 0..self.vram_banks() {
+            let vram = &self.vram[bank * VRAM_BANK_SIZE..];
+            for index in 0..TILES_PER_BANK {
+                // Tiles 256-383 are only reachable with 0x8800 addressing.
+                let (tile, select) = ((index % 256) as u8, index >= 256);
+                let (left, top) = (bank * bank_width + index % TILE_VIEW_COLUMNS * TILE_WIDTH, index / TILE_VIEW_COLUMNS * TILE_HEIGHT);
+                for y in 0..TILE_HEIGHT {
+                    for x in 0..TILE_WIDTH {
+                        let color = Self::get_tile_pixel_color(tile, x, y, vram, select);
+                        image.set(left + x, top + y, self.view_color(color, 0));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // Get the tile at (x,y) in the tile view.
+    pub fn tile_view_info(&self, x: usize, y: usize) -> Option<TileInfo> {
+        let bank_width = TILE_VIEW_COLUMNS * TILE_WIDTH;
+        let (bank, index) = (x / bank_width, y / TILE_HEIGHT * TILE_VIEW_COLUMNS + x % bank_width / TILE_WIDTH);
+        if bank >= self.vram_banks() || index >= TILES_PER_BANK {
+            return None;
+        }
+        Some(TileInfo { map_address: None, tile: (index % 256) as u8, index, bank, address: 0x8000 + index as u16 * 16 })
+    }
+
+    // Draw the tile maps at 0x9800 and 0x9C00 side by side, using the tile data addressing and
+    // attributes the background would, with the part of the background on screen (per SCX and
+    // SCY) outlined on the map LCDC selects for it.
+    pub fn map_view(&self) -> Image {
+        let mut image = Image::new(BG_WIDTH * 2, BG_HEIGHT);
+        for map in 0..2 {
+            let tilemap = 0x1800 + map * TILEMAP_SIZE;
+            for y in 0..BG_HEIGHT {
+                for x in 0..BG_WIDTH {
+                    let pixel = self.bg_pixel(tilemap, x, y);
+                    image.set(map * BG_WIDTH + x, y, self.view_color(pixel.color, pixel.attrs));
+                }
+            }
+        }
+
+        let left = if self.lcdc & LCDC_BG9C00 != 0 { BG_WIDTH } else { 0 };
+        let mut outline = |x: usize, y: usize| {
+            let (x, y) = ((x + self.scx as usize) % BG_WIDTH, (y + self.scy as usize) % BG_HEIGHT);
+            image.set(left + x, y, VIEWPORT_COLOR);
+        };
+        for x in 0..SCREEN_WIDTH {
+            outline(x, 0);
+            outline(x, SCREEN_HEIGHT - 1);
+        }
+        for y in 0..SCREEN_HEIGHT {
+            outline(0, y);
+            outline(SCREEN_WIDTH - 1, y);
+        }
+        image
+    }
+
+    // Get the tile at (x,y) in the tile map view.
+    pub fn map_view_info(&self, x: usize, y: usize) -> Option<TileInfo> {
+        if x >= BG_WIDTH * 2 || y >= BG_HEIGHT {
+            return None;
+        }
+        let entry = 0x1800 + x / BG_WIDTH * TILEMAP_SIZE + y / TILE_HEIGHT * TILEMAP_WIDTH + x % BG_WIDTH / TILE_WIDTH;
+        let tile = self.vram[entry];
+        let attrs = if self.cgb_mode { self.vram[VRAM_BANK_SIZE + entry] } else { 0 };
+        let index = Self::tile_data_index(tile, self.lcdc & LCDC_BG8000 == 0);
+        Some(TileInfo {
+            map_address: Some(0x8000 + entry as u16),
+            tile,
+            index,
+            bank: (attrs & BG_BANK != 0) as usize,
+            address: 0x8000 + index as u16 * 16,
+        })
+    }
+}
+
+// An OAM entry, and what happened to it in the last frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpriteInfo {
+    pub index: usize,       // Position in OAM
+    pub x: isize,           // Screen position of the left edge
+    pub y: isize,           // Screen position of the top edge
+    pub tile: u8,
+    pub attrs: u8,
+    pub color: bool,        // Whether the attributes have the color mode meaning
+    pub lines: Vec<usize>,  // Lines it was drawn on
+    pub dropped: Vec<usize>, // Lines it was left out of by the 10 sprite limit
+}
+
+impl fmt::Display for SpriteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sprite {}: x {} y {} tile {:02X} attrs {:02X}", self.index, self.x, self.y, self.tile, self.attrs)?;
+        let mut flags = Vec::new();
+        if self.attrs & OBJ_BGPRIO != 0 {
+            flags.push("behind BG".to_string());
+        }
+        if self.attrs & OBJ_YFLIP != 0 {
+            flags.push("Y flip".to_string());
+        }
+        if self.attrs & OBJ_XFLIP != 0 {
+            flags.push("X flip".to_string());
+        }
+        if self.color {
+            flags.push(format!("bank {} palette {}", (self.attrs & OBJ_BANK != 0) as u8, self.attrs & OBJ_CGBPALETTE));
+        } else {
+            flags.push(if self.attrs & OBJ_PALETTE != 0 { "OBP1" } else { "OBP0" }.to_string());
+        }
+        write!(f, " ({})", flags.join(", "))?;
+        if !self.lines.is_empty() {
+            write!(f, ", lines {}", line_ranges(&self.lines))?;
+        }
+        if !self.dropped.is_empty() {
+            write!(f, ", dropped on {}", line_ranges(&self.dropped))?;
+        }
+        Ok(())
+    }
+}
+
+// Format a sorted list of lines like "0-7, 12, 20-35".
+fn line_ranges(lines: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == line => *end = line,
+            _ => ranges.push((line, line)),
+        }
+    }
+    let ranges: Vec<String> = ranges.iter().map(|&(start, end)| {
+        if start == end { start.to_string() } else { format!("{}-{}", start, end) }
+    }).collect();
+    ranges.join(", ")
+}
+
+impl PPU {
+    // Note which sprites the OAM scan finds on the current line.
+    pub(super) fn record_oam_scan(&mut self) {
+        let mut scan = OamScan { line: self.ly as usize, ..OamScan::default() };
+        if self.lcdc & LCDC_OBJON != 0 {
+            for (i, sprite) in self.sprites_overlapping(self.ly as usize).enumerate() {
+                if i < MAX_SPRITES_PER_LINE {
+                    scan.drawn |= 1 << sprite.index;
+                } else {
+                    scan.dropped |= 1 << sprite.index;
+                }
+            }
+        }
+        self.oam_scans_back.push(scan);
+    }
+
+    // Decode all 40 OAM entries, with the lines of the last frame each one was drawn on or
+    // dropped from. The lines come from the OAM scan on each line, so they're still right when
+    // OAM is rewritten during the frame.
+    pub fn sprite_info(&self) -> Vec<SpriteInfo> {
+        (0..SPRITE_COUNT).map(|index| self.sprite_info_at(index)).collect()
+    }
+
+    fn sprite_info_at(&self, index: usize) -> SpriteInfo {
+        let sprite = Sprite::from(index, &self.oam[index * 4..index * 4 + 4]);
+        let lines_where = |mask: fn(&OamScan) -> u64| {
+            self.oam_scans.iter().filter(|scan| mask(scan) & 1 << index != 0).map(|scan| scan.line).collect()
+        };
+        SpriteInfo {
+            index,
+            x: sprite.x,
+            y: sprite.y,
+            tile: sprite.tile,
+            attrs: sprite.attrs,
+            color: self.cgb_mode,
+            lines: lines_where(|scan| scan.drawn),
+            dropped: lines_where(|scan| scan.dropped),
+        }
+    }
+
+    // Draw each OAM entry in a column, with the sprite at the current object height at the top,
+    // and below it a chart of the screen's lines marking where it was drawn and where it was
+    // dropped in the last frame.
+    pub fn sprite_view(&self) -> Image {
+        let mut image = Image::new(SPRITE_COLUMN_WIDTH * SPRITE_COUNT, SPRITE_CHART_TOP + SCREEN_HEIGHT);
+        image.fill(GAP_COLOR);
+
+        let obj_height = self.obj_height();
+        for index in 0..SPRITE_COUNT {
+            let sprite = Sprite::from(index, &self.oam[index * 4..index * 4 + 4]);
+            let left = index * SPRITE_COLUMN_WIDTH;
+            for y in 0..obj_height {
+                for x in 0..TILE_WIDTH as isize {
+                    let pixel = sprite.pixel_at(sprite.x + x, sprite.y + y, obj_height, &self.vram, self.cgb_mode);
+                    image.set(left + x as usize, y as usize, self.sprite_color(pixel));
+                }
+            }
+
+            for y in 0..SCREEN_HEIGHT {
+                for x in 0..TILE_WIDTH {
+                    image.set(left + x, SPRITE_CHART_TOP + y, UNUSED_COLOR);
+                }
+            }
+        }
+
+        for scan in &self.oam_scans {
+            for index in 0..SPRITE_COUNT {
+                let color = if scan.drawn & 1 << index != 0 {
+                    DRAWN_COLOR
+                } else if scan.dropped & 1 << index != 0 {
+                    DROPPED_COLOR
+                } else {
+                    continue;
+                };
+                for x in 0..TILE_WIDTH {
+                    image.set(index * SPRITE_COLUMN_WIDTH + x, SPRITE_CHART_TOP + scan.line, color);
+                }
+            }
+        }
+        image
+    }
+
+    // Color of a sprite pixel in the sprite view. Unlike on screen, color 0 is shown.
+    fn sprite_color(&self, pixel: ObjPixel) -> Pixel {
+        if self.cgb_mode {
+            Self::rgb555(&self.obpd, pixel.attrs & OBJ_CGBPALETTE, pixel.color)
+        } else if pixel.attrs & OBJ_PALETTE == 0 {
+            self.shade(pixel.color, self.obp0, Layer::Obj0, &self.obpd, 0)
+        } else {
+            self.shade(pixel.color, self.obp1, Layer::Obj1, &self.obpd, 1)
+        }
+    }
+
+    // Get the OAM entry whose column is at x in the sprite view.
+    pub fn sprite_view_info(&self, x: usize, y: usize) -> Option<SpriteInfo> {
+        let index = x / SPRITE_COLUMN_WIDTH;
+        if index >= SPRITE_COUNT || x % SPRITE_COLUMN_WIDTH >= TILE_WIDTH || y >= SPRITE_CHART_TOP + SCREEN_HEIGHT {
+            return None;
+        }
+        Some(self.sprite_info_at(index))
+    }
+}
@@ -0,0 +1,360 @@
+// Pixel art scaling filters, run on the CPU so they can be used with any output. They take an
+// RGBA frame and produce one scaled up by the filter's factor.
+
+use std::str::FromStr;
+
+type Rgba = [u8; 4];
+
+// Largest factor() of any filter.
+const MAX_FACTOR: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    #[default] None,
+    Scale2x,
+    Scale3x,
+    Hq2x,
+    Hq3x,
+    Hq4x,
+    Xbr,        // xBR at 2x
+    LcdGrid,    // Each pixel drawn as a dot on a dark grid, at 3x
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Filter::None),
+            "scale2x" => Ok(Filter::Scale2x),
+            "scale3x" => Ok(Filter::Scale3x),
+            "hq2x" => Ok(Filter::Hq2x),
+            "hq3x" => Ok(Filter::Hq3x),
+            "hq4x" => Ok(Filter::Hq4x),
+            "xbr" => Ok(Filter::Xbr),
+            "lcd" => Ok(Filter::LcdGrid),
+            _ => Err(format!("unknown filter '{}'", s)),
+        }
+    }
+}
+
+impl Filter {
+    pub const ALL: [Filter; 8] = [
+        Filter::None, Filter::Scale2x, Filter::Scale3x, Filter::Hq2x, Filter::Hq3x, Filter::Hq4x,
+        Filter::Xbr, Filter::LcdGrid,
+    ];
+
+    // The next filter, for cycling through them.
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&f| f == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    // How many times larger the output is in each direction.
+    pub fn factor(self) -> usize {
+        match self {
+            Filter::None => 1,
+            Filter::Scale2x | Filter::Hq2x | Filter::Xbr => 2,
+            Filter::Scale3x | Filter::Hq3x | Filter::LcdGrid => 3,
+            Filter::Hq4x => 4,
+        }
+    }
+
+    // Scale a width x height RGBA frame into out, which must be factor() times larger in each
+    // direction.
+    pub fn apply(self, src: &[u8], width: usize, height: usize, out: &mut [u8]) {
+        let n = self.factor();
+        assert_eq!(src.len(), width * height * 4);
+        assert_eq!(out.len(), src.len() * n * n);
+
+        let frame = Frame { data: src, width, height };
+        let mut block = [[0; 4]; MAX_FACTOR * MAX_FACTOR];
+        let block = &mut block[..n * n];
+        for y in 0..height {
+            for x in 0..width {
+                match self {
+                    Filter::None => block[0] = frame.get(x, y, 0, 0),
+                    Filter::Scale2x => block.copy_from_slice(&scale2x(&frame.neighbors(x, y))),
+                    Filter::Scale3x => block.copy_from_slice(&scale3x(&frame.neighbors(x, y))),
+                    Filter::Hq2x | Filter::Hq3x | Filter::Hq4x => hqx(&frame.neighbors(x, y), n, block),
+                    Filter::Xbr => block.copy_from_slice(&xbr(&frame, x, y)),
+                    Filter::LcdGrid => block.copy_from_slice(&lcd_grid(frame.get(x, y, 0, 0))),
+                }
+                for (i, pixel) in block.iter().enumerate() {
+                    let (ox, oy) = (x * n + i % n, y * n + i / n);
+                    let offset = (oy * width * n + ox) * 4;
+                    out[offset..offset + 4].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+}
+
+struct Frame<'a> {
+    data: &'a [u8],
+    width: usize,
+    height: usize,
+}
+
+impl Frame<'_> {
+    // Get the pixel at an offset from (x,y), repeating the edge pixels outside the frame.
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> Rgba {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        let offset = (y * self.width + x) * 4;
+        self.data[offset..offset + 4].try_into().unwrap()
+    }
+
+    // Get the 3x3 block around (x,y), in reading order.
+    fn neighbors(&self, x: usize, y: usize) -> [Rgba; 9] {
+        std::array::from_fn(|i| self.get(x, y, i as isize % 3 - 1, i as isize / 3 - 1))
+    }
+}
+
+// Mix colors with the given weights.
+fn blend(colors: &[(Rgba, u32)]) -> Rgba {
+    let total: u32 = colors.iter().map(|&(_, w)| w).sum();
+    std::array::from_fn(|c| {
+        let sum: u32 = colors.iter().map(|&(color, w)| color[c] as u32 * w).sum();
+        ((sum + total / 2) / total) as u8
+    })
+}
+
+fn yuv(color: Rgba) -> [i32; 3] {
+    let [r, g, b] = [color[0] as i32, color[1] as i32, color[2] as i32];
+    [(r + g + b) / 3, (r - b) / 4 + 128, (2 * g - r - b) / 8 + 128]
+}
+
+// Whether two colors look different, using the thresholds of the hqx filters.
+fn differ(a: Rgba, b: Rgba) -> bool {
+    let (a, b) = (yuv(a), yuv(b));
+    (a[0] - b[0]).abs() > 48 || (a[1] - b[1]).abs() > 7 || (a[2] - b[2]).abs() > 6
+}
+
+// Perceptual distance between two colors, as used by xBR.
+fn distance(a: Rgba, b: Rgba) -> u32 {
+    let (a, b) = (yuv(a), yuv(b));
+    (48 * (a[0] - b[0]).abs() + 7 * (a[1] - b[1]).abs() + 6 * (a[2] - b[2]).abs()) as u32
+}
+
+// Scale2x (AdvanceMAME): extend edges that pass diagonally through a pixel's corners.
+fn scale2x(w: &[Rgba; 9]) -> [Rgba; 4] {
+    let [_, b, _, d, e, f, _, h, _] = *w;
+    if b == h || d == f {
+        return [e; 4];
+    }
+    [
+        if d == b { d } else { e },
+        if b == f { f } else { e },
+        if d == h { d } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+// Scale3x (AdvanceMAME): like Scale2x, with the edge pixels following the same lines.
+fn scale3x(w: &[Rgba; 9]) -> [Rgba; 9] {
+    let [a, b, c, d, e, f, g, h, i] = *w;
+    if b == h || d == f {
+        return [e; 9];
+    }
+    [
+        if d == b { d } else { e },
+        if (d == b && e != c) || (b == f && e != a) { b } else { e },
+        if b == f { f } else { e },
+        if (d == b && e != g) || (d == h && e != a) { d } else { e },
+        e,
+        if (b == f && e != i) || (h == f && e != c) { f } else { e },
+        if d == h { d } else { e },
+        if (d == h && e != i) || (h == f && e != g) { h } else { e },
+        if h == f { f } else { e },
+    ]
+}
+
+// hqx (Maxim Stepin's hq2x, hq3x and hq4x), writing an n x n block into out. Each neighbor is
+// compared with the pixel, and the pattern of those that differ looks up a rule for the top
+// left corner of the block in HQ_RULES. The other corners use the same table, with the
+// neighborhood turned a quarter at a time so each one in turn is at the top left.
+fn hqx(w: &[Rgba; 9], n: usize, out: &mut [Rgba]) {
+    let e = w[4];
+    let same = |p, q| !differ(p, q);
+    let differs = w.map(|c| differ(e, c));
+
+    // Weighted color sums for each output pixel, as 3x shares its edge pixels between corners.
+    let mut sums = [([0; 4], 0); MAX_FACTOR * MAX_FACTOR];
+    for (neighbors, (cx, cy), b_dir, d_dir) in HQ_CORNERS {
+        let pattern = neighbors.iter().enumerate().fold(0, |p, (bit, &i)| p | (differs[i] as usize) << bit);
+        let [a, b, _, d, f, _, h, _] = neighbors.map(|i| w[i]);
+        let shape = match HQ_RULES[pattern] {
+            1 => Shape::Diagonal,
+            2 => Shape::SideD,
+            3 => Shape::SideB,
+            4 => Shape::Smooth,
+            5 => Shape::DiagonalB,
+            6 => Shape::DiagonalD,
+            rule @ 12..=17 if same(b, d) => {
+                [Shape::Edge, Shape::EdgeSharp, Shape::EdgeFaint, Shape::Edge, Shape::EdgeSoft, Shape::EdgeSharp][rule as usize - 12]
+            }
+            12..=14 => Shape::Center,
+            15..=17 => Shape::Diagonal,
+            18 if same(b, f) => Shape::SlopeB,
+            18 => Shape::SideD,
+            19 if same(d, h) => Shape::SlopeD,
+            19 => Shape::SideB,
+            _ => Shape::Center,
+        } as usize;
+
+        // Output pixels of this corner, as steps in from its outer pixel away from the D and B
+        // sides, with their weights for e, a, b and d.
+        let steps = [(0, 0), (1, 0), (0, 1), (1, 1)];
+        let (steps, weights): (&[_], &[Weights]) = match n {
+            2 => (&steps[..1], &HQ2X[shape..=shape]),
+            3 => (&steps[..3], &HQ3X[shape]),
+            _ => (&steps, &HQ4X[shape]),
+        };
+        let outer = (cx * (n - 1) as isize, cy * (n - 1) as isize);
+        for (&(i, j), weights) in steps.iter().zip(weights) {
+            let (x, y) = (outer.0 - i * d_dir.0 - j * b_dir.0, outer.1 - i * d_dir.1 - j * b_dir.1);
+            let (sum, total) = &mut sums[y as usize * n + x as usize];
+            for (color, weight) in [e, a, b, d].into_iter().zip(weights) {
+                for c in 0..4 {
+                    sum[c] += color[c] as u32 * weight;
+                }
+            }
+            *total += 16;
+        }
+    }
+    for (pixel, (sum, total)) in out.iter_mut().zip(sums) {
+        *pixel = if total == 0 { e } else { sum.map(|s| ((s + total / 2) / total) as u8) };
+    }
+}
+
+// The corners of the block for hqx: the neighbors in the order of the pattern bits once the
+// corner is turned to the top left (a, b, c, d, f, g, h, i, reading order without the center),
+// the corner's position, and the outward directions of its b and d sides.
+type HqCorner = ([usize; 8], (isize, isize), (isize, isize), (isize, isize));
+const HQ_CORNERS: [HqCorner; 4] = [
+    ([0, 1, 2, 3, 5, 6, 7, 8], (0, 0), (0, -1), (-1, 0)),
+    ([2, 5, 8, 1, 7, 0, 3, 6], (1, 0), (1, 0), (0, -1)),
+    ([8, 7, 6, 5, 3, 2, 1, 0], (1, 1), (0, 1), (1, 0)),
+    ([6, 3, 0, 7, 1, 8, 5, 2], (0, 1), (-1, 0), (0, 1)),
+];
+
+// hq2x's rule for the top left corner by pattern:
+// 1-6: blend with a, d, b, d and b, a and b, or a and d
+// 12-14: along the b-d edge if b and d match, with decreasing weight, else keep the pixel
+// 15-17: the same, else blend with a
+// 18: towards b if it continues into f, else blend with d
+// 19: towards d if it continues into h, else blend with b
+const HQ_RULES: [u8; 256] = [
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 12, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 16, 14,
+    4, 4, 6, 18, 4, 4, 6, 18, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19, 12, 12, 5, 19, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5, 19,  1, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6, 18, 5,  3, 16, 12, 5, 19,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 15, 12, 5,  3, 17, 13,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 17, 13, 5,  3, 16, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 13, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3, 16, 13,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3,  1, 12,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3, 16, 12, 5,  3,  1, 14,
+    4, 4, 6,  2, 4, 4, 6,  2, 5,  3,  1, 12, 5,  3,  1, 14,
+];
+
+// What a rule comes to once its conditions are checked, indexing the weight tables.
+#[derive(Clone, Copy)]
+enum Shape {
+    Center,
+    Diagonal,
+    SideB,
+    SideD,
+    Smooth,
+    DiagonalB,
+    DiagonalD,
+    SlopeB,
+    SlopeD,
+    EdgeFaint,
+    EdgeSoft,
+    Edge,
+    EdgeSharp,
+}
+
+// Sixteenths of e, a, b and d in an output pixel.
+type Weights = [u32; 4];
+
+// The corner pixel at 2x.
+const HQ2X: [Weights; 13] = [
+    [16, 0, 0, 0], [12, 4, 0, 0], [12, 0, 4, 0], [12, 0, 0, 4], [8, 0, 4, 4], [8, 4, 4, 0], [8, 4, 0, 4],
+    [10, 0, 4, 2], [10, 0, 2, 4], [14, 0, 1, 1], [12, 0, 2, 2], [8, 0, 4, 4], [4, 0, 6, 6],
+];
+
+// The corner pixel and the edge pixels next to it on its b and d sides at 3x.
+const HQ3X: [[Weights; 3]; 13] = [
+    [[16, 0, 0, 0], [16, 0, 0, 0], [16, 0, 0, 0]],
+    [[12, 4, 0, 0], [16, 0, 0, 0], [16, 0, 0, 0]],
+    [[12, 0, 4, 0], [12, 0, 4, 0], [16, 0, 0, 0]],
+    [[12, 0, 0, 4], [16, 0, 0, 0], [12, 0, 0, 4]],
+    [[8, 0, 4, 4], [12, 0, 4, 0], [12, 0, 0, 4]],
+    [[12, 4, 0, 0], [12, 0, 4, 0], [16, 0, 0, 0]],
+    [[12, 4, 0, 0], [16, 0, 0, 0], [12, 0, 0, 4]],
+    [[10, 0, 4, 2], [4, 0, 12, 0], [16, 0, 0, 0]],
+    [[10, 0, 2, 4], [16, 0, 0, 0], [4, 0, 0, 12]],
+    [[12, 0, 2, 2], [16, 0, 0, 0], [16, 0, 0, 0]],
+    [[8, 0, 4, 4], [14, 0, 2, 0], [14, 0, 0, 2]],
+    [[2, 0, 7, 7], [14, 0, 2, 0], [14, 0, 0, 2]],
+    [[0, 0, 8, 8], [8, 0, 8, 0], [8, 0, 0, 8]],
+];
+
+// The 2x2 quarter of the block at 4x: the corner pixel, the pixels next to it on its b and d
+// sides, and the inner one.
+const HQ4X: [[Weights; 4]; 13] = [
+    [[16, 0, 0, 0], [16, 0, 0, 0], [16, 0, 0, 0], [16, 0, 0, 0]],
+    [[10, 6, 0, 0], [12, 4, 0, 0], [12, 4, 0, 0], [14, 2, 0, 0]],
+    [[10, 0, 6, 0], [12, 0, 4, 0], [14, 0, 2, 0], [14, 0, 2, 0]],
+    [[10, 0, 0, 6], [14, 0, 0, 2], [12, 0, 0, 4], [14, 0, 0, 2]],
+    [[8, 0, 4, 4], [10, 0, 4, 2], [10, 0, 2, 4], [12, 0, 2, 2]],
+    [[8, 4, 4, 0], [12, 0, 4, 0], [12, 4, 0, 0], [14, 2, 0, 0]],
+    [[8, 4, 0, 4], [12, 4, 0, 0], [12, 0, 0, 4], [14, 2, 0, 0]],
+    [[12, 0, 4, 0], [4, 0, 12, 0], [16, 0, 0, 0], [16, 0, 0, 0]],
+    [[12, 0, 0, 4], [16, 0, 0, 0], [4, 0, 0, 12], [16, 0, 0, 0]],
+    [[12, 0, 2, 2], [14, 0, 2, 0], [14, 0, 0, 2], [16, 0, 0, 0]],
+    [[6, 0, 5, 5], [14, 0, 2, 0], [14, 0, 0, 2], [14, 0, 1, 1]],
+    [[0, 0, 8, 8], [8, 0, 8, 0], [8, 0, 0, 8], [16, 0, 0, 0]],
+    [[0, 0, 8, 8], [4, 0, 8, 4], [4, 0, 4, 8], [8, 0, 4, 4]],
+];
+
+// xBR (level 1) at 2x. For each corner, an edge is found by comparing the color distances
+// along both diagonals of a 5x5 block, and the corner is blended towards the closer neighbor
+// across it.
+fn xbr(frame: &Frame, x: usize, y: usize) -> [Rgba; 4] {
+    let e = frame.get(x, y, 0, 0);
+    let mut out = [e; 4];
+    // Rotate the block so each corner in turn is at the bottom right.
+    for [xx, xy, yx, yy] in [[1, 0, 0, 1], [0, -1, 1, 0], [-1, 0, 0, -1], [0, 1, -1, 0]] {
+        let rotate = |dx: isize, dy: isize| (xx * dx + xy * dy, yx * dx + yy * dy);
+        let p = |dx, dy| {
+            let (dx, dy) = rotate(dx, dy);
+            frame.get(x, y, dx, dy)
+        };
+        let (b, c, d, f, g, h, i) = (p(0, -1), p(1, -1), p(-1, 0), p(1, 0), p(-1, 1), p(0, 1), p(1, 1));
+        let (f4, h5, i4, i5) = (p(2, 0), p(0, 2), p(2, 1), p(1, 2));
+
+        let across = distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+        let along = distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+        if across < along {
+            let nearest = if distance(e, f) <= distance(e, h) { f } else { h };
+            let (cx, cy) = rotate(1, 1);
+            out[((cx + 1) / 2 + (cy + 1)) as usize] = blend(&[(e, 1), (nearest, 1)]);
+        }
+    }
+    out
+}
+
+// Draw a pixel as a 3x3 dot, with the gap to the right and below it darkened.
+fn lcd_grid(e: Rgba) -> [Rgba; 9] {
+    let gap = blend(&[(e, 3), ([0, 0, 0, e[3]], 2)]);
+    [e, e, gap, e, e, gap, gap, gap, gap]
+}
@@ -1,120 +1,339 @@
-use rand::Rng;
+use std::ptr;
 
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use crate::bus::{BusDevice, Page, Ram, PAGE_SIZE};
+use crate::cartridge::Cartridge;
+use crate::colorize::{Colorization, Combo};
+use crate::joypad::Joypad;
+use crate::model::Model;
 use crate::ppu::PPU;
 
-const ROM_SIZE: usize = 32 * 1024;
-const VRAM_SIZE: usize = 8 * 1024;
-const EXTRAM_SIZE: usize = 8 * 1024;
-const WRAM_SIZE: usize = 8 * 1024;
 const OAM_SIZE: usize = 160;
-const HRAM_SIZE: usize = 127;
+
+// Marks an address with no device attached in the bus map.
+const UNMAPPED: u8 = 0xFF;
+
+const PAGES: usize = 0x10000 / PAGE_SIZE;
+
+// Each 16-byte block of a VRAM DMA transfer takes the same time in both speeds, so twice as many
+// CPU cycles in double speed mode.
+const HDMA_BLOCK_DOTS: usize = 32;
 
 pub struct System {
-    pub ppu: PPU,
+    pub model: Model,
+    pub boot_combo: Option<Combo>,  // Buttons held at boot to pick a DMG game's colors on a CGB
+
+    devices: Vec<Box<dyn BusDevice>>,
+    map: Vec<u8>,   // Index of the device handling each address
 
-    rom: Vec<u8>,
-    vram: Vec<u8>,
-    extram: Vec<u8>,
-    wram: Vec<u8>,
-    oam: Vec<u8>,
-    hram: Vec<u8>,
+    // Direct pointers to the plain memory behind each 256-byte page, or null if accesses to the
+    // page have to go through the device (IO registers, ROM writes, partially mapped pages).
+    read_pages: [*const u8; PAGES],
+    write_pages: [*mut u8; PAGES],
+    pages_stale: bool,  // The page table was cleared while a device was borrowed, rebuild it
 
     ireq: u8,
     ie: u8,
+
+    hdma_src: u16,  // VRAM DMA source address
+    hdma_dst: u16,  // VRAM DMA destination address
+    hdma5: u8,      // VRAM DMA length/mode/status
+    stall: usize,   // Clock cycles the CPU must wait for DMA or a speed switch
+    key1: u8,       // Speed switch register (color mode)
 }
 
+// SAFETY: the page table only points into memory owned by the devices, which are Send and move
+// to the other thread along with the system. System isn't Sync, so the pointers are never used
+// from two threads at once.
+unsafe impl Send for System {}
+
 impl System {
-    pub fn new () -> Self {
-        Self {
-            ppu: PPU::new(),
-            rom: vec![0; ROM_SIZE],
-            vram: (0..VRAM_SIZE).map(|_| rand::rng().random()).collect(),
-            extram: (0..EXTRAM_SIZE).map(|_| rand::rng().random()).collect(),
-            wram: (0..WRAM_SIZE).map(|_| rand::rng().random()).collect(),
-            oam: (0..OAM_SIZE).map(|_| rand::rng().random()).collect(),
-            hram: (0..HRAM_SIZE).map(|_| rand::rng().random()).collect(),
-            ireq: 0,
+    // Create a system of the given model, with the IO registers it handles in their post-boot
+    // state. For the registers emulated here, that's the same on every model. Memory that starts
+    // out as junk is filled from a random number generator seeded with seed, so runs with the same
+    // seed are reproducible, or with a random seed if it's None.
+    pub fn new(model: Model, seed: Option<u64>) -> Self {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        let mut system = Self {
+            model,
+            boot_combo: None,
+            devices: Vec::new(),
+            map: vec![UNMAPPED; 0x10000],
+            read_pages: [ptr::null(); PAGES],
+            write_pages: [ptr::null_mut(); PAGES],
+            pages_stale: false,
+            ireq: 0xE1,
             ie: 0,
+            hdma_src: 0,
+            hdma_dst: 0,
+            hdma5: 0xFF,
+            stall: 0,
+            key1: 0,
+        };
+        system.attach(Box::new(Cartridge::new(&mut rng)));
+        system.attach(Box::new(PPU::new(model, &mut rng)));
+        system.attach(Box::new(Joypad::new()));
+        system.attach(Box::new(Ram::new(0xC000, 0x2000, &mut rng)));
+        system.attach(Box::new(Ram::new(0xFF80, 0x7F, &mut rng)));
+        system
+    }
+
+    // Attach a device to the bus and return its index. It takes over its address ranges from any
+    // previously attached device.
+    pub fn attach(&mut self, device: Box<dyn BusDevice>) -> usize {
+        let index = self.devices.len();
+        assert!(index < UNMAPPED as usize);
+        self.devices.push(device);
+        self.remap();
+        index
+    }
+
+    // Replace the device at the given index with the result of passing it through wrap, for
+    // instance to insert a logging or breakpoint layer in front of it.
+    pub fn wrap_device<F>(&mut self, index: usize, wrap: F)
+    where F: FnOnce(Box<dyn BusDevice>) -> Box<dyn BusDevice> {
+        let placeholder: Box<dyn BusDevice> = Box::new(Ram::new(0, 1, &mut rand::rng()));
+        let device = std::mem::replace(&mut self.devices[index], placeholder);
+        self.devices[index] = wrap(device);
+        self.remap();
+    }
+
+    // Rebuild the bus map from the attached devices' address ranges.
+    fn remap(&mut self) {
+        self.map.fill(UNMAPPED);
+        for (index, device) in self.devices.iter().enumerate() {
+            for range in device.ranges() {
+                for addr in range {
+                    self.map[addr as usize] = index as u8;
+                }
+            }
         }
+        self.remap_pages();
+    }
+
+    // Rebuild the page table from the pages the devices expose. A page is only accessed directly
+    // if every address in it belongs to the device providing it. This has to be done whenever a
+    // device is attached, replaced, reports a remap or is handed out mutably.
+    fn remap_pages(&mut self) {
+        self.clear_pages();
+        self.pages_stale = false;
+        for (index, device) in self.devices.iter_mut().enumerate() {
+            // SAFETY: the pointers are dropped by clear_pages() in all the cases the contract
+            // lists, and only used by read and write, which hold the whole system borrowed.
+            for (page, mapping) in unsafe { device.pages() } {
+                let start = page as usize * PAGE_SIZE;
+                if self.map[start..start+PAGE_SIZE].iter().any(|&i| i as usize != index) {
+                    continue;
+                }
+                match mapping {
+                    Page::ReadOnly(p) => self.read_pages[page as usize] = p,
+                    Page::ReadWrite(p) => {
+                        self.read_pages[page as usize] = p;
+                        self.write_pages[page as usize] = p;
+                    },
+                }
+            }
+        }
+    }
+
+    // Stop accessing memory directly, going through the devices instead.
+    fn clear_pages(&mut self) {
+        self.read_pages.fill(ptr::null());
+        self.write_pages.fill(ptr::null_mut());
+    }
+
+    // Get the first attached device of type T.
+    pub fn device<T: BusDevice>(&self) -> Option<&T> {
+        self.devices.iter().find_map(|d| d.as_any().downcast_ref::<T>())
+    }
+
+    // Get the first attached device of type T mutably. Since the device could change its memory
+    // in any way, memory is accessed through the devices until the page table is rebuilt on the
+    // next tick.
+    pub fn device_mut<T: BusDevice>(&mut self) -> Option<&mut T> {
+        self.clear_pages();
+        self.pages_stale = true;
+        self.find_device_mut()
+    }
+
+    // Like device_mut, for internal uses that don't change the device's pages.
+    fn find_device_mut<T: BusDevice>(&mut self) -> Option<&mut T> {
+        self.devices.iter_mut().find_map(|d| d.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        self.device::<PPU>().expect("no PPU attached")
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut PPU {
+        self.device_mut::<PPU>().expect("no PPU attached")
+    }
+
+    pub fn joypad_mut(&mut self) -> &mut Joypad {
+        self.device_mut::<Joypad>().expect("no joypad attached")
     }
 
     pub fn read(&self, addr: u16) -> u8 {
+        let page = self.read_pages[addr as usize / PAGE_SIZE];
+        if !page.is_null() {
+            // SAFETY: the page table only holds pointers the devices' pages() contract covers.
+            return unsafe { *page.add(addr as usize % PAGE_SIZE) };
+        }
+        self.read_slow(addr)
+    }
+
+    fn read_slow(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..0x8000 => self.rom[addr as usize],
-            0x8000..0xA000 => self.vram[addr as usize - 0x8000],
-            0xA000..0xC000 => self.extram[addr as usize - 0xA000],
-            0xC000..0xE000 => self.wram[addr as usize - 0xC000],
-            0xE000..0xFE00 => 0xFF,
-            0xFE00..0xFEA0 => self.oam[addr as usize - 0xFE00],
-            0xFEA0..0xFF00 => 0x00,
             0xFF0F => self.ireq,
-            0xFF40 => self.ppu.lcdc,
-            0xFF41 => self.ppu.get_stat(),
-            0xFF42 => self.ppu.scy,
-            0xFF43 => self.ppu.scx,
-            0xFF44 => self.ppu.ly,
-            0xFF45 => self.ppu.lyc,
-            0xFF47 => self.ppu.bgp,
-            0xFF48 => self.ppu.obp0,
-            0xFF49 => self.ppu.obp1,
-            0xFF4A => self.ppu.wy,
-            0xFF4B => self.ppu.wx,
-            0xFF68 => self.ppu.bgpi,
-            0xFF69 => self.ppu.get_bgpd(),
-            0xFF6A => self.ppu.obpi,
-            0xFF6B => self.ppu.get_obpd(),
-            0xFF80..0xFFFF => self.hram[addr as usize - 0xFF80],
+            0xFF4D if self.model.is_cgb() => self.key1 | 0x7E,
+            0xFF55 if self.model.is_cgb() => self.hdma5,
             0xFFFF => self.ie,
-            _ => 0xFF,
+            _ => match self.map[addr as usize] {
+                UNMAPPED => 0xFF,
+                index => self.devices[index as usize].read(addr),
+            },
         }
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
+        let page = self.write_pages[addr as usize / PAGE_SIZE];
+        if !page.is_null() {
+            // SAFETY: the page table only holds pointers the devices' pages() contract covers.
+            unsafe { *page.add(addr as usize % PAGE_SIZE) = data };
+            return;
+        }
+        self.write_slow(addr, data);
+    }
+
+    fn write_slow(&mut self, addr: u16, data: u8) {
         match addr {
-            0x8000..0xA000 => self.vram[addr as usize - 0x8000] = data,
-            0xA000..0xC000 => self.extram[addr as usize - 0xA000] = data,
-            0xC000..0xE000 => self.wram[addr as usize - 0xC000] = data,
-            0xFE00..0xFEA0 => self.oam[addr as usize - 0xFE00] = data,
             0xFF0F => self.ireq = data,
-            0xFF40 => self.ppu.lcdc = data,
-            0xFF41 => self.ppu.set_stat(data),
-            0xFF42 => self.ppu.scy = data,
-            0xFF43 => self.ppu.scx = data,
-            0xFF44 => self.ppu.ly = data,
-            0xFF45 => self.ppu.lyc = data,
-            0xFF47 => self.ppu.bgp = data,
-            0xFF48 => self.ppu.obp0 = data,
-            0xFF49 => self.ppu.obp1 = data,
-            0xFF4A => self.ppu.wy = data,
-            0xFF4B => self.ppu.wx = data,
-            0xFF68 => self.ppu.bgpi = data,
-            0xFF69 => self.ppu.set_bgpd(data),
-            0xFF6A => self.ppu.obpi = data,
-            0xFF6B => self.ppu.set_obpd(data),
-            0xFF80..0xFFFF => self.hram[addr as usize - 0xFF80] = data,
+            0xFF4D | 0xFF51..=0xFF55 if !self.model.is_cgb() => (),
+            0xFF4D => self.key1 = (self.key1 & 0x80) | (data & 0x01),
+            0xFF51 => self.hdma_src = (self.hdma_src & 0x00F0) | (data as u16) << 8,
+            0xFF52 => self.hdma_src = (self.hdma_src & 0xFF00) | (data & 0xF0) as u16,
+            0xFF53 => self.hdma_dst = (self.hdma_dst & 0x00F0) | ((data & 0x1F) as u16) << 8,
+            0xFF54 => self.hdma_dst = (self.hdma_dst & 0x1F00) | (data & 0xF0) as u16,
+            0xFF55 => self.start_hdma(data),
             0xFFFF => self.ie = data,
 
             // OAM DMA transfer
             0xFF46 => {
                 let src_addr = (data as u16) << 8;
-                for i in 0..OAM_SIZE {
-                    self.oam[i] = self.read(src_addr + i as u16);
+                for i in 0..OAM_SIZE as u16 {
+                    self.write(0xFE00 + i, self.read(src_addr + i));
                 }
             },
 
-            _ => (),
+            _ => match self.map[addr as usize] {
+                UNMAPPED => (),
+                index => {
+                    let device = &mut self.devices[index as usize];
+                    device.write(addr, data);
+                    if device.take_remap() {
+                        self.remap_pages();
+                    }
+                },
+            },
         };
     }
 
-    pub fn load_rom(&mut self, path: &str) {
-        let rom = std::fs::read(path).unwrap();
-        self.rom.copy_from_slice(&rom);
+    // Advance every device by the given number of clock cycles, collecting their interrupt
+    // requests.
+    pub fn tick(&mut self, cycles: usize) {
+        if self.pages_stale {
+            self.remap_pages();
+        }
+        for device in self.devices.iter_mut() {
+            self.ireq |= device.tick(cycles);
+        }
+        if self.hdma5 & 0x80 == 0 && self.find_device_mut::<PPU>().expect("no PPU attached").take_hblank_start() {
+            self.hdma_hblank();
+        }
+    }
+
+    // Handle a write to HDMA5. Bit 7 selects HBlank DMA (1) or general-purpose DMA (0), and the
+    // low 7 bits are the number of 16-byte blocks to transfer minus one. Writing with bit 7 clear
+    // while an HBlank DMA is running cancels it instead.
+    fn start_hdma(&mut self, data: u8) {
+        if self.hdma5 & 0x80 == 0 {
+            if data & 0x80 == 0 {
+                self.hdma5 |= 0x80;
+            }
+            return;
+        }
+
+        if data & 0x80 == 0 {
+            let blocks = (data & 0x7F) as usize + 1;
+            for _ in 0..blocks {
+                self.hdma_copy_block();
+            }
+            self.stall += blocks * HDMA_BLOCK_DOTS * self.speed_factor();
+            self.hdma5 = 0xFF;
+        } else {
+            self.hdma5 = data & 0x7F;
+        }
+    }
+
+    // Copy the next 16 bytes of a VRAM DMA transfer.
+    fn hdma_copy_block(&mut self) {
+        for _ in 0..16 {
+            self.write(0x8000 | self.hdma_dst, self.read(self.hdma_src));
+            self.hdma_src = self.hdma_src.wrapping_add(1);
+            self.hdma_dst = (self.hdma_dst + 1) & 0x1FFF;
+        }
+    }
+
+    // Transfer one block of an active HBlank DMA. Called at the start of each HBlank period.
+    fn hdma_hblank(&mut self) {
+        if self.hdma5 & 0x80 != 0 {
+            return;
+        }
+        self.hdma_copy_block();
+        self.stall += HDMA_BLOCK_DOTS * self.speed_factor();
+        // Decrementing past zero leaves 0xFF, which marks the transfer as finished.
+        self.hdma5 = self.hdma5.wrapping_sub(1);
+    }
+
+    // Called when the CPU executes STOP. If a speed switch was requested through KEY1, toggle
+    // between normal and double speed and return true; otherwise return false.
+    pub fn stop(&mut self) -> bool {
+        if self.key1 & 0x01 == 0 {
+            return false;
+        }
+        self.key1 = (self.key1 ^ 0x80) & 0x80;
+        // The CPU is stopped for 2050 machine cycles while the clock switches over.
+        self.stall += 8200;
+        true
+    }
+
+    // Number of CPU clock cycles per PPU dot: 2 in double speed mode, otherwise 1. Everything
+    // clocked by the CPU (OAM DMA included) runs at this multiple of the PPU rate.
+    pub fn speed_factor(&self) -> usize {
+        if self.key1 & 0x80 != 0 { 2 } else { 1 }
+    }
+
+    // Take the number of CPU clock cycles the CPU is stalled for by DMA or a speed switch.
+    pub fn take_stall(&mut self) -> usize {
+        std::mem::take(&mut self.stall)
     }
 
-    pub fn draw_scanline(&mut self, framebuf: &mut [u8]) {
-        self.ppu.draw_scanline(framebuf, &mut self.vram, &mut self.oam);
-        if self.ppu.ly == 144 {
-            self.ireq |= 0x1;
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.device_mut::<Cartridge>().expect("no cartridge attached").load_rom(rom)?;
+        self.remap_pages();
+
+        // Cartridges without color support run in DMG compatibility mode on a CGB, colorized the
+        // way the boot ROM would.
+        if self.model.is_cgb() && self.read(0x0143) & 0x80 == 0 {
+            let header: Vec<u8> = (0x0100..0x0150).map(|addr| self.read(addr)).collect();
+            let colors = Colorization::from_header(&header, self.boot_combo);
+            self.ppu_mut().set_dmg_compatibility(&colors);
         }
+        Ok(())
     }
 }
@@ -0,0 +1,157 @@
+// Recording every emulated frame to a video file, timed at the console's real frame rate
+// (4194304 / 70224 = ~59.73 Hz) rather than the speed the frontend ran at.
+//
+// Two formats are supported, picked by file extension: animated PNG (.png or .apng), which is
+// lossless, and YUV4MPEG2 (.y4m), which is uncompressed and readable by most video tools, but
+// stores 4:4:4 YUV so colors are rounded slightly.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use crate::ppu::CYCLES_PER_FRAME;
+
+const CLOCK_RATE: usize = 4194304;
+
+// APNG frame delays are 16-bit fractions, so use the closest one to the frame time, which drifts
+// by about one frame every three weeks of footage.
+const APNG_DELAY: (u16, u16) = (400, 23891);
+
+enum Format {
+    Apng {
+        actl_offset: u64,   // Position of the acTL chunk, to fill in the frame count at the end
+        sequence: u32,      // Next APNG sequence number
+    },
+    Y4m,
+}
+
+pub struct VideoRecorder {
+    file: BufWriter<File>,
+    format: Format,
+    width: usize,
+    height: usize,
+    frames: u32,
+    finished: bool,
+}
+
+impl VideoRecorder {
+    // Start recording RGBA frames of the given size to a file.
+    pub fn create(path: &Path, width: usize, height: usize) -> io::Result<Self> {
+        let extension = path.extension().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+        let mut file = BufWriter::new(File::create(path)?);
+        let format = match extension.as_str() {
+            "png" | "apng" => {
+                file.write_all(b"\x89PNG\r\n\x1a\n")?;
+                let mut ihdr = Vec::new();
+                ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+                ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+                ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8-bit RGBA, no interlacing
+                write_chunk(&mut file, b"IHDR", &ihdr)?;
+                let actl_offset = file.stream_position()?;
+                write_chunk(&mut file, b"acTL", &[0; 8])?;
+                Format::Apng { actl_offset, sequence: 0 }
+            },
+            "y4m" => {
+                writeln!(file, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, CLOCK_RATE, CYCLES_PER_FRAME)?;
+                Format::Y4m
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown video format (use .png, .apng or .y4m)")),
+        };
+        Ok(Self { file, format, width, height, frames: 0, finished: false })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // Append a frame of RGBA pixels.
+    pub fn add_frame(&mut self, rgba: &[u8]) -> io::Result<()> {
+        assert_eq!(rgba.len(), self.width * self.height * 4);
+        match &mut self.format {
+            Format::Apng { sequence, .. } => {
+                let mut fctl = Vec::new();
+                fctl.extend_from_slice(&sequence.to_be_bytes());
+                fctl.extend_from_slice(&(self.width as u32).to_be_bytes());
+                fctl.extend_from_slice(&(self.height as u32).to_be_bytes());
+                fctl.extend_from_slice(&[0; 8]); // x and y offsets
+                fctl.extend_from_slice(&APNG_DELAY.0.to_be_bytes());
+                fctl.extend_from_slice(&APNG_DELAY.1.to_be_bytes());
+                fctl.extend_from_slice(&[0, 0]); // No disposal, replace the previous frame
+                write_chunk(&mut self.file, b"fcTL", &fctl)?;
+                *sequence += 1;
+
+                // Each row is prefixed with filter type 0 (none).
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+                for row in rgba.chunks_exact(self.width * 4) {
+                    encoder.write_all(&[0])?;
+                    encoder.write_all(row)?;
+                }
+                let data = encoder.finish()?;
+
+                // The first frame is the default image, later ones go in numbered fdAT chunks.
+                if self.frames == 0 {
+                    write_chunk(&mut self.file, b"IDAT", &data)?;
+                } else {
+                    let mut fdat = sequence.to_be_bytes().to_vec();
+                    fdat.extend_from_slice(&data);
+                    write_chunk(&mut self.file, b"fdAT", &fdat)?;
+                    *sequence += 1;
+                }
+            },
+            Format::Y4m => {
+                // BT.601 studio range, which players assume for Y4M.
+                let pixels = || rgba.chunks_exact(4).map(|p| [p[0] as i32, p[1] as i32, p[2] as i32]);
+                let y = pixels().map(|[r, g, b]| (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+                let u = pixels().map(|[r, g, b]| (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+                let v = pixels().map(|[r, g, b]| (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+                self.file.write_all(b"FRAME\n")?;
+                self.file.write_all(&y.chain(u).chain(v).collect::<Vec<u8>>())?;
+            },
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Finish the file. An APNG needs at least one frame. Dropping a recorder without calling this
+    // finishes the file too, but errors are lost.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finalize()
+    }
+
+    fn finalize(&mut self) -> io::Result<()> {
+        self.finished = true;
+        if let Format::Apng { actl_offset, .. } = self.format {
+            if self.frames == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "no frames recorded"));
+            }
+            write_chunk(&mut self.file, b"IEND", &[])?;
+            // Fill in the frame count, and play once.
+            let mut actl = self.frames.to_be_bytes().to_vec();
+            actl.extend_from_slice(&1u32.to_be_bytes());
+            self.file.seek(SeekFrom::Start(actl_offset))?;
+            write_chunk(&mut self.file, b"acTL", &actl)?;
+        }
+        self.file.flush()
+    }
+}
+
+impl Drop for VideoRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.finalize();
+        }
+    }
+}
+
+fn write_chunk(file: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    file.write_all(&(data.len() as u32).to_be_bytes())?;
+    file.write_all(kind)?;
+    file.write_all(data)?;
+    file.write_all(&crc.finalize().to_be_bytes())
+}
@@ -0,0 +1,125 @@
+// A debugging window next to the main one, showing what's in VRAM and OAM. Hovering over it shows
+// details of what's under the pointer in the title.
+
+use std::sync::Arc;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::dpi::LogicalSize;
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoopWindowTarget;
+use winit::window::{Window, WindowBuilder, WindowId};
+
+use rgb::GameBoy;
+use rgb::frame::Image;
+use rgb::palette::Palette;
+
+const SCALE: f64 = 2.0;
+
+// What the viewer shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    Tiles,  // All tiles in VRAM
+    Maps,   // Both tile maps
+    Sprites, // All OAM entries and the lines they were on
+}
+
+impl View {
+    // The next view, or None after the last one, for cycling through them.
+    pub fn next(self) -> Option<Self> {
+        match self {
+            View::Tiles => Some(View::Maps),
+            View::Maps => Some(View::Sprites),
+            View::Sprites => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            View::Tiles => "tiles",
+            View::Maps => "tile maps",
+            View::Sprites => "sprites",
+        }
+    }
+
+    fn render(self, gameboy: &GameBoy) -> Image {
+        let ppu = gameboy.system().ppu();
+        match self {
+            View::Tiles => ppu.tile_view(),
+            View::Maps => ppu.map_view(),
+            View::Sprites => ppu.sprite_view(),
+        }
+    }
+
+    // Describe what's at (x,y) in the view.
+    fn info(self, gameboy: &GameBoy, x: usize, y: usize) -> Option<String> {
+        let ppu = gameboy.system().ppu();
+        match self {
+            View::Tiles => ppu.tile_view_info(x, y).map(|info| info.to_string()),
+            View::Maps => ppu.map_view_info(x, y).map(|info| info.to_string()),
+            View::Sprites => ppu.sprite_view_info(x, y).map(|info| info.to_string()),
+        }
+    }
+}
+
+pub struct Viewer {
+    pub view: View,
+    window: Arc<Window>,
+    pixels: Pixels<'static>,
+    cursor: Option<(usize, usize)>,     // Pixel of the view under the pointer
+}
+
+impl Viewer {
+    pub fn new(view: View, gameboy: &GameBoy, target: &EventLoopWindowTarget<()>) -> Result<Self, String> {
+        let image = view.render(gameboy);
+        let window = WindowBuilder::new()
+            .with_title(format!("rgb {}", view.name()))
+            .with_inner_size(LogicalSize::new(image.width as f64 * SCALE, image.height as f64 * SCALE))
+            .with_resizable(false)
+            .build(target)
+            .map_err(|e| e.to_string())?;
+        let window = Arc::new(window);
+        let winsize = window.inner_size();
+        let surface = SurfaceTexture::new(winsize.width, winsize.height, window.clone());
+        let pixels = Pixels::new(image.width as u32, image.height as u32, surface).map_err(|e| e.to_string())?;
+        Ok(Self { view, window, pixels, cursor: None })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    // Handle an event for the viewer's window. Returns false once the window should close.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::CloseRequested => return false,
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = self.pixels.window_pos_to_pixel((position.x as f32, position.y as f32)).ok();
+            },
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::Resized(size) if self.pixels.resize_surface(size.width, size.height).is_err() => {
+                eprintln!("pixels resize error");
+                return false;
+            },
+            WindowEvent::RedrawRequested if self.pixels.render().is_err() => {
+                eprintln!("pixels render error");
+                return false;
+            },
+            _ => (),
+        }
+        true
+    }
+
+    // Redraw the view with the console's current state.
+    pub fn update(&mut self, gameboy: &GameBoy, palette: &Palette) {
+        self.view.render(gameboy).to_rgba(palette, self.pixels.frame_mut());
+        let info = self.cursor.and_then(|(x, y)| self.view.info(gameboy, x, y));
+        let title = match info {
+            Some(info) => format!("rgb {} - {}", self.view.name(), info),
+            None => format!("rgb {}", self.view.name()),
+        };
+        if self.window.title() != title {
+            self.window.set_title(&title);
+        }
+        self.window.request_redraw();
+    }
+}